@@ -0,0 +1,434 @@
+use crate::{
+    compression::{xz_tar_entries, XzBlocks, TAR_OBJECT},
+    error::BundleError,
+};
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use tar::{Builder, Header};
+
+/// The version of the bundle layout written by this crate. Bundles with a
+/// higher version are refused rather than misread.
+pub const BUNDLE_VERSION: u32 = 1;
+pub const MANIFEST_NAME: &str = "manifest.json";
+/// Environment variable naming a bundle on disk to use instead of the
+/// embedded one, when it is newer.
+pub const BUNDLE_PATH_VARIABLE: &str = "CALIFORNIA_WATER_BUNDLE";
+const OBS_DATE_FORMAT: &str = "%Y%m%d";
+// held by the tests that read or set `CALIFORNIA_WATER_BUNDLE`, as every
+// test thread shares it
+#[cfg(test)]
+pub(crate) static BUNDLE_PATH_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// What the manifest records about one csv file of a bundle.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub name: String,
+    pub stations: Vec<String>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub rows: usize,
+    pub sha256: String,
+}
+
+impl ManifestFile {
+    /// Describes a csv file of CDEC rows, e.g.
+    /// `SHA,D,15,STORAGE,19850101 0000,19850101 0000,1543200,,AF`.
+    /// A `STATION_ID,...` header row is allowed and skipped.
+    pub fn describe(name: &str, contents: &[u8]) -> Self {
+        let mut stations: BTreeSet<String> = BTreeSet::new();
+        let mut start_date: Option<NaiveDate> = None;
+        let mut end_date: Option<NaiveDate> = None;
+        let mut rows = 0usize;
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(contents);
+        for record in reader.records().flatten() {
+            let station_id = record.get(0).unwrap_or("");
+            if station_id.is_empty() || station_id == "STATION_ID" {
+                continue;
+            }
+            rows += 1;
+            if !stations.contains(station_id) {
+                stations.insert(String::from(station_id));
+            }
+            let date = record
+                .get(5)
+                .and_then(|date| date.get(0..8))
+                .and_then(|date| NaiveDate::parse_from_str(date, OBS_DATE_FORMAT).ok());
+            if let Some(date) = date {
+                start_date = Some(start_date.map_or(date, |start| start.min(date)));
+                end_date = Some(end_date.map_or(date, |end| end.max(date)));
+            }
+        }
+        ManifestFile {
+            name: String::from(name),
+            stations: stations.into_iter().collect(),
+            start_date,
+            end_date,
+            rows,
+            sha256: sha256_hex(contents),
+        }
+    }
+}
+
+/// The table of contents of a bundle.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub created: NaiveDate,
+    pub files: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    pub fn stations(&self) -> BTreeSet<String> {
+        self.files
+            .iter()
+            .flat_map(|file| file.stations.iter().cloned())
+            .collect()
+    }
+
+    pub fn start_date(&self) -> Option<NaiveDate> {
+        self.files.iter().filter_map(|file| file.start_date).min()
+    }
+
+    pub fn end_date(&self) -> Option<NaiveDate> {
+        self.files.iter().filter_map(|file| file.end_date).max()
+    }
+
+    /// Whether a bundle with this manifest should be preferred over one with
+    /// `other`: it reaches further forward in time, or was made later.
+    pub fn is_newer_than(&self, other: &Manifest) -> bool {
+        (self.end_date(), self.created) > (other.end_date(), other.created)
+    }
+}
+
+/// One file packed in a bundle.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BundleEntry {
+    pub name: String,
+    pub contents: Vec<u8>,
+}
+
+/// A snapshot of historical observations: csv files of CDEC rows and a
+/// manifest describing them, packed as an xz compressed tar.
+///
+/// The manifest is the first entry of the tar. Snapshots made before
+/// bundles had manifests are still read, with a manifest worked out from
+/// their contents.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DatasetBundle {
+    pub manifest: Manifest,
+    pub entries: Vec<BundleEntry>,
+}
+
+impl DatasetBundle {
+    pub fn new(created: NaiveDate, entries: Vec<BundleEntry>) -> Self {
+        let files = entries
+            .iter()
+            .map(|entry| ManifestFile::describe(entry.name.as_str(), entry.contents.as_slice()))
+            .collect();
+        DatasetBundle {
+            manifest: Manifest {
+                version: BUNDLE_VERSION,
+                created,
+                files,
+            },
+            entries,
+        }
+    }
+
+    /// Reads an xz compressed tar. The checksums are not checked, see
+    /// `verify`.
+    pub fn from_xz_tar(input: &[u8]) -> Result<Self, BundleError> {
+        let mut manifest: Option<Manifest> = None;
+        let mut entries: Vec<BundleEntry> = Vec::new();
+        for (name, contents) in xz_tar_entries(input)? {
+            if name == MANIFEST_NAME {
+                manifest = Some(serde_json::from_slice(&contents).map_err(BundleError::Manifest)?);
+            } else {
+                entries.push(BundleEntry { name, contents });
+            }
+        }
+        match manifest {
+            Some(manifest) if manifest.version > BUNDLE_VERSION => {
+                Err(BundleError::Version(manifest.version))
+            }
+            Some(manifest) => Ok(DatasetBundle { manifest, entries }),
+            None => {
+                // an older snapshot, dated by the last day it covers
+                let mut bundle = DatasetBundle::new(NaiveDate::default(), entries);
+                if let Some(end_date) = bundle.manifest.end_date() {
+                    bundle.manifest.created = end_date;
+                }
+                Ok(bundle)
+            }
+        }
+    }
+
+    /// The bundle compiled into the crate.
+    pub fn embedded() -> Self {
+        DatasetBundle::from_xz_tar(TAR_OBJECT).expect("embedded bundle is unreadable")
+    }
+
+    /// Reads and verifies a bundle on disk.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BundleError> {
+        let input = std::fs::read(path)?;
+        let bundle = DatasetBundle::from_xz_tar(input.as_slice())?;
+        bundle.verify()?;
+        Ok(bundle)
+    }
+
+    /// The bundle at `path` if it is newer than this one, otherwise this
+    /// one. Fails when `path` can not be read or does not verify, rather
+    /// than quietly falling back to this one.
+    pub fn or_newer<P: AsRef<Path>>(self, path: Option<P>) -> Result<Self, BundleError> {
+        match path.map(DatasetBundle::load).transpose()? {
+            Some(bundle) if bundle.manifest.is_newer_than(&self.manifest) => Ok(bundle),
+            _ => Ok(self),
+        }
+    }
+
+    /// The embedded bundle, or the one named by `CALIFORNIA_WATER_BUNDLE`
+    /// when that is newer, see `or_newer`.
+    pub fn current() -> Result<Self, BundleError> {
+        let path = std::env::var(BUNDLE_PATH_VARIABLE).ok().map(PathBuf::from);
+        DatasetBundle::embedded().or_newer(path)
+    }
+
+    /// The xz compressed bytes of `current`, for reading a block at a
+    /// time, see `stream::ObservationStream::current`. Only the manifests
+    /// are read to pick one, so neither bundle is decoded as a whole and
+    /// the checksums of the manifest are not checked.
+    pub fn current_xz() -> Result<Cow<'static, [u8]>, BundleError> {
+        let path = match std::env::var(BUNDLE_PATH_VARIABLE) {
+            Ok(path) => path,
+            Err(_) => return Ok(Cow::Borrowed(TAR_OBJECT)),
+        };
+        let input = std::fs::read(path)?;
+        let embedded = read_xz_manifest(TAR_OBJECT)?;
+        if read_xz_manifest(input.as_slice())?.is_newer_than(&embedded) {
+            Ok(Cow::Owned(input))
+        } else {
+            Ok(Cow::Borrowed(TAR_OBJECT))
+        }
+    }
+
+    /// Checks that the entries are exactly the files of the manifest, with
+    /// the checksums it lists.
+    pub fn verify(&self) -> Result<(), BundleError> {
+        for file in self.manifest.files.iter() {
+            let entry = self
+                .entries
+                .iter()
+                .find(|entry| entry.name == file.name)
+                .ok_or_else(|| BundleError::MissingEntry(file.name.clone()))?;
+            let found = sha256_hex(entry.contents.as_slice());
+            if found != file.sha256 {
+                return Err(BundleError::Checksum {
+                    name: file.name.clone(),
+                    expected: file.sha256.clone(),
+                    found,
+                });
+            }
+        }
+        for entry in self.entries.iter() {
+            if !self
+                .manifest
+                .files
+                .iter()
+                .any(|file| file.name == entry.name)
+            {
+                return Err(BundleError::UnlistedEntry(entry.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every csv entry, one after the other.
+    pub fn csv(&self) -> Vec<u8> {
+        let mut csv: Vec<u8> = Vec::new();
+        for entry in self.entries.iter() {
+            csv.extend_from_slice(entry.contents.as_slice());
+            if !csv.is_empty() && !csv.ends_with(b"\n") {
+                csv.push(b'\n');
+            }
+        }
+        csv
+    }
+
+    /// The uncompressed tar, manifest first; xz compress it to get a bundle
+    /// `from_xz_tar` reads.
+    pub fn to_tar(&self) -> Result<Vec<u8>, BundleError> {
+        let manifest = serde_json::to_vec_pretty(&self.manifest).map_err(BundleError::Manifest)?;
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, MANIFEST_NAME, manifest.as_slice())?;
+        for entry in self.entries.iter() {
+            append_file(&mut builder, entry.name.as_str(), entry.contents.as_slice())?;
+        }
+        Ok(builder.into_inner()?)
+    }
+}
+
+fn append_file(
+    builder: &mut Builder<Vec<u8>>,
+    name: &str,
+    contents: &[u8],
+) -> Result<(), BundleError> {
+    let mut header = Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, contents)?;
+    Ok(())
+}
+
+// the manifest of an xz compressed bundle, decoding only the blocks that
+// hold its first tar entry; an older snapshot without one is decoded in
+// full to date it, see `from_xz_tar`
+fn read_xz_manifest(input: &[u8]) -> Result<Manifest, BundleError> {
+    let mut blocks = XzBlocks::new(input)?;
+    let mut head: Vec<u8> = Vec::new();
+    let mut fill = |head: &mut Vec<u8>, length: usize| -> Result<(), BundleError> {
+        while head.len() < length {
+            match blocks.next() {
+                Some(block) => head.extend_from_slice(block?.as_slice()),
+                None => return Err(BundleError::Container(String::from("truncated tar"))),
+            }
+        }
+        Ok(())
+    };
+    fill(&mut head, 512)?;
+    let header = Header::from_byte_slice(&head[..512]);
+    if header.path()?.as_ref() != Path::new(MANIFEST_NAME) {
+        return Ok(DatasetBundle::from_xz_tar(input)?.manifest);
+    }
+    let size = header.size()? as usize;
+    fill(&mut head, 512 + size)?;
+    let manifest: Manifest =
+        serde_json::from_slice(&head[512..512 + size]).map_err(BundleError::Manifest)?;
+    if manifest.version > BUNDLE_VERSION {
+        return Err(BundleError::Version(manifest.version));
+    }
+    Ok(manifest)
+}
+
+fn sha256_hex(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BundleEntry, DatasetBundle, ManifestFile};
+    use crate::{compression::decompress_tar_file_to_csv_string, error::BundleError};
+    use chrono::NaiveDate;
+    use lzma_rs::xz_compress;
+    use std::path::Path;
+    pub static TAR_TEST_OBJECT: &[u8] = include_bytes!("../test-fixtures/output.tar.lzma");
+
+    const SHA_CSV: &str = "SHA,D,15,STORAGE,19850101 0000,19850101 0000,1543200,,AF
+SHA,D,15,STORAGE,19850102 0000,19850102 0000,---,,AF
+";
+    const VIL_CSV: &str = "VIL,D,15,STORAGE,20220215 0000,20220215 0000,9593,,AF
+";
+
+    fn entries() -> Vec<BundleEntry> {
+        vec![
+            BundleEntry {
+                name: String::from("sha.csv"),
+                contents: SHA_CSV.as_bytes().to_vec(),
+            },
+            BundleEntry {
+                name: String::from("vil.csv"),
+                contents: VIL_CSV.as_bytes().to_vec(),
+            },
+        ]
+    }
+
+    fn xz(tar: Vec<u8>) -> Vec<u8> {
+        let mut output = Vec::new();
+        xz_compress(&mut tar.as_slice(), &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_describe() {
+        let file = ManifestFile::describe("sha.csv", SHA_CSV.as_bytes());
+        assert_eq!(file.stations, vec![String::from("SHA")]);
+        assert_eq!(file.rows, 2);
+        assert_eq!(file.start_date, NaiveDate::from_ymd_opt(1985, 1, 1));
+        assert_eq!(file.end_date, NaiveDate::from_ymd_opt(1985, 1, 2));
+        assert_eq!(file.sha256.len(), 64);
+    }
+
+    #[test]
+    fn test_round_trip_and_verify() {
+        let created = NaiveDate::from_ymd_opt(2022, 6, 1).unwrap();
+        let bundle = DatasetBundle::new(created, entries());
+        let bytes = xz(bundle.to_tar().unwrap());
+        let read = DatasetBundle::from_xz_tar(bytes.as_slice()).unwrap();
+        assert_eq!(read, bundle);
+        assert_eq!(
+            decompress_tar_file_to_csv_string(bytes.as_slice()),
+            read.csv()
+        );
+        read.verify().unwrap();
+        assert_eq!(read.manifest.stations().len(), 2);
+        assert_eq!(
+            read.manifest.end_date(),
+            NaiveDate::from_ymd_opt(2022, 2, 15)
+        );
+        assert_eq!(read.csv(), format!("{}{}", SHA_CSV, VIL_CSV).into_bytes());
+        let mut tampered = bundle.clone();
+        tampered.entries[1].contents = b"VIL,D,15".to_vec();
+        assert!(matches!(
+            tampered.verify(),
+            Err(BundleError::Checksum { .. })
+        ));
+    }
+
+    #[test]
+    fn test_legacy_snapshot() {
+        let bundle = DatasetBundle::from_xz_tar(TAR_TEST_OBJECT).unwrap();
+        assert_eq!(bundle.entries.len(), 1);
+        bundle.verify().unwrap();
+        assert_eq!(Some(bundle.manifest.created), bundle.manifest.end_date());
+    }
+
+    #[test]
+    fn test_embedded() {
+        let embedded = DatasetBundle::embedded();
+        embedded.verify().unwrap();
+        assert!(embedded.manifest.stations().contains("SHA"));
+    }
+
+    #[test]
+    fn test_or_newer() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bundle.tar.xz");
+        let created = NaiveDate::from_ymd_opt(2022, 6, 1).unwrap();
+        let fallback = DatasetBundle::new(created, entries());
+        // no path, or nothing newer, keeps the fallback
+        let no_path: Option<&Path> = None;
+        assert_eq!(fallback.clone().or_newer(no_path).unwrap(), fallback);
+        std::fs::write(&path, xz(fallback.to_tar().unwrap())).unwrap();
+        let older = DatasetBundle::new(created, entries()[0..1].to_vec());
+        assert_eq!(fallback.clone().or_newer(Some(&path)).unwrap(), fallback);
+        assert_eq!(older.clone().or_newer(Some(&path)).unwrap(), fallback);
+        // a path that is given has to hold a bundle
+        std::fs::write(&path, b"not a bundle").unwrap();
+        assert!(older.clone().or_newer(Some(&path)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            older.or_newer(Some(&path)),
+            Err(BundleError::Io(_))
+        ));
+    }
+}
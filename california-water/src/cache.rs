@@ -0,0 +1,611 @@
+use crate::{
+    error::{CacheError, ObservationError},
+    observation::{
+        DataRecording, DateChunks, Duration, Observation, SensorValue, StationObservations,
+    },
+    sensor::Sensor,
+    source::CdecSource,
+};
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord, Writer};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+const CSV_HEADER: [&str; 9] = [
+    "STATION_ID",
+    "DURATION",
+    "SENSOR_NUMBER",
+    "SENSOR_TYPE",
+    "DATE TIME",
+    "OBS DATE",
+    "VALUE",
+    "DATA_FLAG",
+    "UNITS",
+];
+const COVERAGE_HEADER: [&str; 2] = ["START", "END"];
+const COVERAGE_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The station, sensor and duration a cached series belongs to.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct CacheKey {
+    pub station_id: String,
+    pub sensor_number: u32,
+    pub duration: Duration,
+}
+
+impl CacheKey {
+    pub fn new(station_id: &str, sensor: &Sensor, duration: &Duration) -> Self {
+        CacheKey {
+            station_id: station_id.to_uppercase(),
+            sensor_number: sensor.number,
+            duration: *duration,
+        }
+    }
+
+    // e.g. VIL_15_D
+    fn file_stem(&self) -> String {
+        format!(
+            "{}_{}_{}",
+            self.station_id,
+            self.sensor_number,
+            self.duration.code()
+        )
+    }
+
+    fn from_file_stem(stem: &str) -> Option<Self> {
+        let mut parts = stem.rsplitn(3, '_');
+        let duration = Duration::try_from(parts.next()?).ok()?;
+        let sensor_number = parts.next()?.parse::<u32>().ok()?;
+        let station_id = String::from(parts.next()?);
+        Some(CacheKey {
+            station_id,
+            sensor_number,
+            duration,
+        })
+    }
+}
+
+/// A cached series and the dates it is known to cover. Days inside the
+/// coverage without an observation are days CDEC had nothing for; the
+/// coverage ends at the last value CDEC reported.
+#[derive(Debug, Clone)]
+pub struct CacheEntry<V = u32> {
+    pub observations: Vec<Observation<V>>,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+}
+
+/// Observations kept on disk between runs.
+///
+/// Every series is a csv file in CDEC's own layout (`VIL_15_D.csv`) next
+/// to a small csv of the dates it covers (`VIL_15_D.covered.csv`), so the
+/// cache can be read and edited by hand. Asking for a range that reaches
+/// past the coverage only requests the missing days from CDEC.
+#[derive(Debug, Clone)]
+pub struct ObservationCache {
+    pub directory: PathBuf,
+    pub chunks: DateChunks,
+}
+
+impl ObservationCache {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        ObservationCache {
+            directory: directory.as_ref().to_path_buf(),
+            chunks: DateChunks::default(),
+        }
+    }
+
+    pub fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        self.directory.join(format!("{}.csv", key.file_stem()))
+    }
+
+    fn coverage_path(&self, key: &CacheKey) -> PathBuf {
+        self.directory
+            .join(format!("{}.covered.csv", key.file_stem()))
+    }
+
+    /// Reads a cached series, `None` if nothing is cached for `key`.
+    pub fn load<V: SensorValue>(
+        &self,
+        key: &CacheKey,
+    ) -> Result<Option<CacheEntry<V>>, ObservationError> {
+        let coverage_path = self.coverage_path(key);
+        let (start_date, end_date) = match read_coverage(&coverage_path) {
+            Ok(Some(coverage)) => coverage,
+            Ok(None) => return Ok(None),
+            Err(source) => return Err(cache_error(&coverage_path, source)),
+        };
+        let entry_path = self.entry_path(key);
+        let observations = match read_observations(&entry_path) {
+            Ok(Some(observations)) => observations,
+            Ok(None) => return Ok(None),
+            Err(source) => return Err(cache_error(&entry_path, source)),
+        };
+        Ok(Some(CacheEntry {
+            observations,
+            start_date,
+            end_date,
+        }))
+    }
+
+    /// Writes a series, replacing whatever was cached for `key`.
+    pub fn store<V: SensorValue>(
+        &self,
+        key: &CacheKey,
+        entry: &CacheEntry<V>,
+    ) -> Result<(), ObservationError> {
+        fs::create_dir_all(&self.directory)
+            .map_err(|e| cache_error(&self.directory, CacheError::Io(e)))?;
+        let entry_path = self.entry_path(key);
+        write_observations(&entry_path, &entry.observations)
+            .map_err(|source| cache_error(&entry_path, source))?;
+        // the coverage goes last, a series without one is treated as missing
+        let coverage_path = self.coverage_path(key);
+        write_coverage(&coverage_path, &entry.start_date, &entry.end_date)
+            .map_err(|source| cache_error(&coverage_path, source))
+    }
+
+    /// The last date the cached series for `key` covers.
+    pub fn last_date(&self, key: &CacheKey) -> Result<Option<NaiveDate>, ObservationError> {
+        let coverage_path = self.coverage_path(key);
+        match read_coverage(&coverage_path) {
+            Ok(coverage) => Ok(coverage.map(|(_, end_date)| end_date)),
+            Err(source) => Err(cache_error(&coverage_path, source)),
+        }
+    }
+
+    /// Every series in the cache.
+    pub fn keys(&self) -> Result<Vec<CacheKey>, ObservationError> {
+        let read_dir = match fs::read_dir(&self.directory) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(cache_error(&self.directory, CacheError::Io(e))),
+        };
+        let mut keys = Vec::new();
+        for dir_entry in read_dir {
+            let dir_entry =
+                dir_entry.map_err(|e| cache_error(&self.directory, CacheError::Io(e)))?;
+            let file_name = dir_entry.file_name();
+            let stem = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".covered.csv"));
+            if let Some(key) = stem.and_then(CacheKey::from_file_stem) {
+                keys.push(key);
+            }
+        }
+        keys.sort_by_key(|key| key.file_stem());
+        Ok(keys)
+    }
+
+    /// Forgets the series for `key` so the next request fetches it again.
+    pub fn invalidate(&self, key: &CacheKey) -> Result<(), ObservationError> {
+        for path in [self.coverage_path(key), self.entry_path(key)] {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(cache_error(&path, CacheError::Io(e))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets every series of a station, returning the ones removed.
+    pub fn invalidate_station(&self, station_id: &str) -> Result<Vec<CacheKey>, ObservationError> {
+        let station_id = station_id.to_uppercase();
+        self.remove_where(|key, _| key.station_id == station_id)
+    }
+
+    /// Forgets every series whose coverage ends before `date`, returning
+    /// the ones removed.
+    pub fn prune(&self, date: &NaiveDate) -> Result<Vec<CacheKey>, ObservationError> {
+        self.remove_where(|_, end_date| end_date < *date)
+    }
+
+    fn remove_where<F>(&self, remove: F) -> Result<Vec<CacheKey>, ObservationError>
+    where
+        F: Fn(&CacheKey, NaiveDate) -> bool,
+    {
+        let mut removed = Vec::new();
+        for key in self.keys()? {
+            if let Some(end_date) = self.last_date(&key)? {
+                if remove(&key, end_date) {
+                    self.invalidate(&key)?;
+                    removed.push(key);
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// `Observation::get_observations_by_duration`, answered from the cache
+    /// where it can be and from `source` for the days it can not.
+    pub async fn get_observations_by_duration<V: SensorValue>(
+        &self,
+        source: &dyn CdecSource,
+        reservoir_id: &str,
+        sensor: &Sensor,
+        duration: &Duration,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+    ) -> Result<Vec<Observation<V>>, ObservationError> {
+        let key = CacheKey::new(reservoir_id, sensor, duration);
+        let cached = self.load(&key)?;
+        let missing: Vec<(NaiveDate, NaiveDate)> = match &cached {
+            None => vec![(*start_date, *end_date)],
+            Some(entry) => {
+                let mut missing = Vec::new();
+                if *start_date < entry.start_date {
+                    missing.push((*start_date, entry.start_date.pred_opt().unwrap()));
+                }
+                if *end_date > entry.end_date {
+                    missing.push((entry.end_date.succ_opt().unwrap(), *end_date));
+                }
+                missing
+            }
+        };
+        let entry = match cached {
+            Some(entry) if missing.is_empty() => entry,
+            cached => {
+                let mut fetched: Vec<Observation<V>> = Vec::new();
+                for (missing_start, missing_end) in missing.iter() {
+                    fetched.append(
+                        &mut Observation::get_observations_by_duration_in_chunks(
+                            source,
+                            reservoir_id,
+                            sensor,
+                            duration,
+                            missing_start,
+                            missing_end,
+                            &self.chunks,
+                        )
+                        .await?,
+                    );
+                }
+                let (covered_start, covered_end) = match cached {
+                    Some(mut entry) => {
+                        // what was just fetched wins over what was cached
+                        fetched.append(&mut entry.observations);
+                        (
+                            entry.start_date.min(*start_date),
+                            entry.end_date.max(*end_date),
+                        )
+                    }
+                    None => (*start_date, *end_date),
+                };
+                Observation::stitch_observations(&mut fetched);
+                // the days after the last value may be days CDEC has not
+                // reported yet, so they are fetched again next time
+                let last_recording = fetched
+                    .iter()
+                    .filter(|observation| matches!(observation.value, DataRecording::Recording(_)))
+                    .map(|observation| observation.date_observation.date())
+                    .max();
+                let entry = CacheEntry {
+                    observations: fetched,
+                    start_date: covered_start,
+                    end_date: last_recording.map_or(covered_end, |last| covered_end.min(last)),
+                };
+                // with no value at all nothing is known to be covered
+                if last_recording.is_some() {
+                    self.store(&key, &entry)?;
+                }
+                entry
+            }
+        };
+        Ok(entry
+            .observations
+            .into_iter()
+            .filter(|observation| {
+                let date = observation.date_observation.date();
+                date >= *start_date && date <= *end_date
+            })
+            .collect())
+    }
+
+    /// `Observation::get_observations` with both the daily and the monthly
+    /// series going through the cache. As there, a failed monthly request
+    /// leaves the daily observations as they are, next to its error.
+    pub async fn get_observations<V: SensorValue>(
+        &self,
+        source: &dyn CdecSource,
+        reservoir_id: &str,
+        sensor: &Sensor,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+    ) -> Result<StationObservations<V>, ObservationError> {
+        let observations = self
+            .get_observations_by_duration(
+                source,
+                reservoir_id,
+                sensor,
+                &Duration::Daily,
+                start_date,
+                end_date,
+            )
+            .await?;
+        let monthly_observations = self
+            .get_observations_by_duration(
+                source,
+                reservoir_id,
+                sensor,
+                &Duration::Monthly,
+                start_date,
+                end_date,
+            )
+            .await;
+        Ok(StationObservations::from_daily_and_monthly(
+            observations,
+            monthly_observations,
+        ))
+    }
+}
+
+fn cache_error(path: &Path, source: CacheError) -> ObservationError {
+    ObservationError::Cache {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+fn read_coverage(path: &Path) -> Result<Option<(NaiveDate, NaiveDate)>, CacheError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(CacheError::Io(e)),
+    };
+    let bad_coverage = || CacheError::Coverage(contents.clone());
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(contents.as_bytes());
+    let record = match reader.records().next() {
+        Some(record) => record?,
+        None => return Err(bad_coverage()),
+    };
+    let parse = |i: usize| {
+        record
+            .get(i)
+            .and_then(|date| NaiveDate::parse_from_str(date, COVERAGE_DATE_FORMAT).ok())
+    };
+    match (parse(0), parse(1)) {
+        (Some(start_date), Some(end_date)) => Ok(Some((start_date, end_date))),
+        _ => Err(bad_coverage()),
+    }
+}
+
+fn write_coverage(
+    path: &Path,
+    start_date: &NaiveDate,
+    end_date: &NaiveDate,
+) -> Result<(), CacheError> {
+    let mut writer = Writer::from_writer(vec![]);
+    writer.write_record(COVERAGE_HEADER)?;
+    writer.write_record([
+        start_date.format(COVERAGE_DATE_FORMAT).to_string(),
+        end_date.format(COVERAGE_DATE_FORMAT).to_string(),
+    ])?;
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| CacheError::Io(e.into_error()))?;
+    write_atomically(path, &bytes)
+}
+
+fn read_observations<V: SensorValue>(
+    path: &Path,
+) -> Result<Option<Vec<Observation<V>>>, CacheError> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(CacheError::Io(e)),
+    };
+    let mut observations = Vec::new();
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(contents.as_slice());
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map(|position| position.line());
+        let observation =
+            Observation::try_from(record).map_err(|source| CacheError::Row { line, source })?;
+        observations.push(observation);
+    }
+    Ok(Some(observations))
+}
+
+fn write_observations<V: SensorValue>(
+    path: &Path,
+    observations: &[Observation<V>],
+) -> Result<(), CacheError> {
+    let mut writer = Writer::from_writer(vec![]);
+    writer.write_record(CSV_HEADER)?;
+    for observation in observations {
+        let record = StringRecord::try_from(observation.clone())
+            .map_err(|source| CacheError::Row { line: None, source })?;
+        writer.write_record(&record)?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| CacheError::Io(e.into_error()))?;
+    write_atomically(path, &bytes)
+}
+
+// writes next to `path` first so a reader never sees half a file
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), CacheError> {
+    let partial_path = path.with_extension("partial");
+    fs::write(&partial_path, bytes)?;
+    fs::rename(&partial_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CacheEntry, CacheKey, ObservationCache};
+    use crate::{
+        observation::{DataRecording, Duration, Observation},
+        sensor::Sensor,
+        source::FixtureSource,
+    };
+    use chrono::NaiveDate;
+
+    const STR_RESULT: &str = r#"STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+VIL,D,15,STORAGE,20220215 0000,20220215 0000,9593, ,AF
+VIL,D,15,STORAGE,20220216 0000,20220216 0000,9589, ,AF
+VIL,D,15,STORAGE,20220217 0000,20220217 0000,9589, ,AF
+VIL,D,15,STORAGE,20220218 0000,20220218 0000,9585, ,AF
+VIL,D,15,STORAGE,20220219 0000,20220219 0000,9585, ,AF
+VIL,D,15,STORAGE,20220220 0000,20220220 0000,---, ,AF
+VIL,D,15,STORAGE,20220221 0000,20220221 0000,9581, ,AF
+"#;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 2, day).unwrap()
+    }
+
+    #[test]
+    fn test_store_load_and_prune() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ObservationCache::new(directory.path());
+        let key = CacheKey::new("vil", &Sensor::storage(), &Duration::Daily);
+        assert!(cache.load::<u32>(&key).unwrap().is_none());
+        let entry = CacheEntry {
+            observations: vec![Observation::fixture(
+                "VIL",
+                Duration::Daily,
+                date(15),
+                DataRecording::Recording(9593),
+            )],
+            start_date: date(1),
+            end_date: date(15),
+        };
+        cache.store(&key, &entry).unwrap();
+        let contents = std::fs::read_to_string(cache.entry_path(&key)).unwrap();
+        assert!(contents.starts_with("STATION_ID,DURATION,SENSOR_NUMBER"));
+        assert!(contents.contains("VIL,D,15,STORAGE,20220215 0000,20220215 0000,9593"));
+        let loaded = cache.load(&key).unwrap().unwrap();
+        assert_eq!(loaded.observations, entry.observations);
+        assert_eq!(cache.last_date(&key).unwrap(), Some(date(15)));
+        assert_eq!(cache.keys().unwrap(), vec![key.clone()]);
+        assert!(cache.prune(&date(15)).unwrap().is_empty());
+        assert_eq!(cache.prune(&date(16)).unwrap(), vec![key.clone()]);
+        assert!(cache.load::<u32>(&key).unwrap().is_none());
+        cache.store(&key, &entry).unwrap();
+        assert_eq!(cache.invalidate_station("VIL").unwrap(), vec![key.clone()]);
+        assert!(cache.keys().unwrap().is_empty());
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_fetches_only_missing_tail() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ObservationCache::new(directory.path());
+        let mut source = FixtureSource::new();
+        source.insert("VIL", "D", STR_RESULT);
+        let storage = Sensor::storage();
+        let first = cache
+            .get_observations_by_duration::<u32>(
+                &source,
+                "VIL",
+                &storage,
+                &Duration::Daily,
+                &date(15),
+                &date(18),
+            )
+            .await
+            .unwrap();
+        assert_eq!(first.len(), 4);
+        let second = cache
+            .get_observations_by_duration::<u32>(
+                &source,
+                "VIL",
+                &storage,
+                &Duration::Daily,
+                &date(15),
+                &date(21),
+            )
+            .await
+            .unwrap();
+        assert_eq!(second.len(), 7);
+        assert_eq!(second[5].value, DataRecording::Dash);
+        let third = cache
+            .get_observations_by_duration::<u32>(
+                &source,
+                "VIL",
+                &storage,
+                &Duration::Daily,
+                &date(16),
+                &date(20),
+            )
+            .await
+            .unwrap();
+        assert_eq!(third.len(), 5);
+        let requests = source.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].start_date, date(19));
+        assert_eq!(requests[1].end_date, date(21));
+        let key = CacheKey::new("VIL", &storage, &Duration::Daily);
+        assert_eq!(cache.last_date(&key).unwrap(), Some(date(21)));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_refetches_days_not_reported_yet() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ObservationCache::new(directory.path());
+        let mut source = FixtureSource::new();
+        source.insert("VIL", "D", STR_RESULT);
+        let storage = Sensor::storage();
+        for _ in 0..2 {
+            let observations = cache
+                .get_observations_by_duration::<u32>(
+                    &source,
+                    "VIL",
+                    &storage,
+                    &Duration::Daily,
+                    &date(15),
+                    &date(25),
+                )
+                .await
+                .unwrap();
+            assert_eq!(observations.len(), 7);
+        }
+        let key = CacheKey::new("VIL", &storage, &Duration::Daily);
+        assert_eq!(cache.last_date(&key).unwrap(), Some(date(21)));
+        let requests = source.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].start_date, date(22));
+        assert_eq!(requests[1].end_date, date(25));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_empty_fetch_not_cached() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ObservationCache::new(directory.path());
+        let mut source = FixtureSource::new();
+        // only the header
+        let header = STR_RESULT.lines().next().unwrap();
+        source.insert("VIL", "D", header);
+        let storage = Sensor::storage();
+        for _ in 0..2 {
+            let observations = cache
+                .get_observations_by_duration::<u32>(
+                    &source,
+                    "VIL",
+                    &storage,
+                    &Duration::Daily,
+                    &date(15),
+                    &date(25),
+                )
+                .await
+                .unwrap();
+            assert!(observations.is_empty());
+        }
+        assert!(cache.keys().unwrap().is_empty());
+        let requests = source.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].start_date, date(15));
+        assert_eq!(requests[1].end_date, date(25));
+    }
+}
@@ -0,0 +1,218 @@
+//! The statewide storage line chart, drawn on any plotters backend so the
+//! explorer can draw it on a canvas and the cli to png or svg files.
+use crate::observation::Provenance;
+use chrono::{Datelike, NaiveDate};
+use plotters::{coord::Shift, prelude::*};
+use std::{collections::BTreeMap, error::Error, ops::Range};
+
+/// How a chart looks: its size in pixels, title, axis labels and the room
+/// left for the labels along each axis.
+///
+/// Backends that bring their own size, like a canvas, ignore `width` and
+/// `height`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChartOptions {
+    pub width: u32,
+    pub height: u32,
+    pub title: Option<String>,
+    pub x_label: Option<String>,
+    pub y_label: Option<String>,
+    pub x_label_area_size: u32,
+    pub y_label_area_size: u32,
+}
+
+impl Default for ChartOptions {
+    fn default() -> Self {
+        ChartOptions {
+            width: 1024,
+            height: 768,
+            title: None,
+            x_label: None,
+            y_label: None,
+            x_label_area_size: 40,
+            y_label_area_size: 90,
+        }
+    }
+}
+
+// the markers of the days whose values were not measured
+const DERIVED: [(Provenance, RGBColor); 4] = [
+    (Provenance::InterpolatedFromDaily, BLUE),
+    (Provenance::InterpolatedFromMonthly, CYAN),
+    (Provenance::CarriedForward, MAGENTA),
+    (Provenance::Imputed, BLACK),
+];
+
+/// Draws the storage of every day as a line onto a drawing area and
+/// presents it.
+pub fn draw_storage_chart<DB>(
+    area: &DrawingArea<DB, Shift>,
+    values: &BTreeMap<NaiveDate, u32>,
+    options: &ChartOptions,
+) -> Result<(), Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+{
+    draw_storage_chart_with_provenance(area, values, &BTreeMap::new(), options)
+}
+
+/// `draw_storage_chart` with the days whose values were not measured, as
+/// `provenance` has them, marked on the line in a color of their own.
+/// Days missing from `provenance` count as measured.
+pub fn draw_storage_chart_with_provenance<DB>(
+    area: &DrawingArea<DB, Shift>,
+    values: &BTreeMap<NaiveDate, u32>,
+    provenance: &BTreeMap<NaiveDate, Provenance>,
+    options: &ChartOptions,
+) -> Result<(), Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+{
+    let (start_date, end_date) = match (values.keys().next(), values.keys().next_back()) {
+        (Some(start_date), Some(end_date)) => (*start_date, *end_date),
+        _ => return Err("nothing to chart".into()),
+    };
+    let date_range = Range {
+        start: start_date,
+        end: end_date,
+    };
+    let ranged_date: RangedDate<NaiveDate> = date_range.into();
+    let y_max = *values.values().max().unwrap() as f64;
+    let y_min = *values.values().min().unwrap() as f64;
+    let x_labels_amount = (end_date.year() - start_date.year()).max(1) as usize;
+    area.fill(&WHITE)?;
+    let mut builder = ChartBuilder::on(area);
+    builder
+        .margin(20i32)
+        .x_label_area_size(options.x_label_area_size)
+        .y_label_area_size(options.y_label_area_size);
+    if let Some(title) = &options.title {
+        builder.caption(title, ("sans-serif", 30));
+    }
+    let mut chart = builder.build_cartesian_2d(ranged_date, y_min..y_max)?;
+    let mut mesh = chart.configure_mesh();
+    mesh.x_labels(x_labels_amount);
+    if let Some(x_label) = &options.x_label {
+        mesh.x_desc(x_label);
+    }
+    if let Some(y_label) = &options.y_label {
+        mesh.y_desc(y_label);
+    }
+    mesh.draw()?;
+    // populate the chart with the data
+    chart
+        .draw_series(LineSeries::new(
+            values.iter().map(|(date, value)| (*date, *value as f64)),
+            &RED,
+        ))?
+        .label("water")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+    for (kind, color) in DERIVED {
+        let days: Vec<(NaiveDate, f64)> = values
+            .iter()
+            .filter(|(date, _)| provenance.get(date) == Some(&kind))
+            .map(|(date, value)| (*date, *value as f64))
+            .collect();
+        if days.is_empty() {
+            continue;
+        }
+        chart
+            .draw_series(
+                days.into_iter()
+                    .map(|point| Circle::new(point, 3, color.filled())),
+            )?
+            .label(kind.code())
+            .legend(move |(x, y)| Circle::new((x + 10, y), 3, color.filled()));
+    }
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    area.present()?;
+    Ok(())
+}
+
+/// The storage chart as an svg document, for embedding in reports.
+pub fn storage_chart_svg(
+    values: &BTreeMap<NaiveDate, u32>,
+    options: &ChartOptions,
+) -> Result<String, Box<dyn Error>> {
+    let mut svg = String::new();
+    {
+        let backend = SVGBackend::with_string(&mut svg, (options.width, options.height));
+        draw_storage_chart(&backend.into_drawing_area(), values, options)?;
+    }
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{draw_storage_chart_with_provenance, storage_chart_svg, ChartOptions};
+    use crate::observation::Provenance;
+    use chrono::NaiveDate;
+    use plotters::prelude::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_storage_chart_svg() {
+        let values: BTreeMap<NaiveDate, u32> = (1..=10)
+            .map(|day| {
+                (
+                    NaiveDate::from_ymd_opt(2022, 1, day).unwrap(),
+                    1_000_000 + day * 1000,
+                )
+            })
+            .collect();
+        let options = ChartOptions {
+            width: 640,
+            height: 480,
+            title: Some(String::from("Statewide storage")),
+            ..ChartOptions::default()
+        };
+        let svg = storage_chart_svg(&values, &options).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("width=\"640\""));
+        assert!(svg.contains("Statewide storage"));
+        assert!(svg.contains("<polyline"));
+        assert!(storage_chart_svg(&BTreeMap::new(), &options).is_err());
+    }
+
+    #[test]
+    fn test_storage_chart_provenance() {
+        let date = |day: u32| NaiveDate::from_ymd_opt(2022, 1, day).unwrap();
+        let values: BTreeMap<NaiveDate, u32> =
+            (1..=10).map(|day| (date(day), day * 1000)).collect();
+        let mut provenance = BTreeMap::new();
+        provenance.insert(date(4), Provenance::InterpolatedFromDaily);
+        provenance.insert(date(5), Provenance::InterpolatedFromDaily);
+        provenance.insert(date(10), Provenance::CarriedForward);
+        let options = ChartOptions::default();
+        let mut svg = String::new();
+        {
+            let backend = SVGBackend::with_string(&mut svg, (options.width, options.height));
+            draw_storage_chart_with_provenance(
+                &backend.into_drawing_area(),
+                &values,
+                &provenance,
+                &options,
+            )
+            .unwrap();
+        }
+        assert!(svg.contains("interpolated-daily"));
+        assert!(svg.contains("carried-forward"));
+        assert!(!svg.contains("imputed"));
+        // a marker for each derived day and one for each legend entry
+        assert_eq!(svg.matches("<circle").count(), 5);
+        // measured days are left unmarked
+        assert_eq!(
+            storage_chart_svg(&values, &options)
+                .unwrap()
+                .matches("<circle")
+                .count(),
+            0
+        );
+    }
+}
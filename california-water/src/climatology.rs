@@ -0,0 +1,341 @@
+//! What storage usually is on a day of the year, from the historical
+//! snapshot, so that a day can be put as a percent of its average.
+use crate::{
+    observation::{DataRecording, Duration, Observation},
+    reservoir::Reservoir,
+    sensor::Sensor,
+    source::CdecSource,
+};
+use chrono::{Datelike, NaiveDate};
+use csv::StringRecord;
+use std::collections::{BTreeMap, HashMap};
+
+/// Every value seen on one day of the year, one a year, sorted.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DayOfYear {
+    pub values: Vec<u32>,
+}
+
+impl DayOfYear {
+    pub fn mean(&self) -> Option<f64> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let sum: u64 = self.values.iter().map(|value| *value as u64).sum();
+        Some(sum as f64 / self.values.len() as f64)
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        self.percentile(50.0)
+    }
+
+    /// The `percentile` (0 to 100) of the values, linearly interpolated
+    /// between the two closest ranks.
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let rank = percentile.clamp(0.0, 100.0) / 100.0 * (self.values.len() - 1) as f64;
+        let below = rank.floor() as usize;
+        let above = rank.ceil() as usize;
+        let fraction = rank - below as f64;
+        let low = self.values[below] as f64;
+        let high = self.values[above] as f64;
+        Some(low + (high - low) * fraction)
+    }
+}
+
+/// Day of year statistics of one daily series, keyed by (month, day) so
+/// that the 29th of February keeps to itself and the days after it line
+/// up across leap years.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Climatology {
+    pub days: BTreeMap<(u32, u32), DayOfYear>,
+}
+
+impl Climatology {
+    pub fn from_values(values: &BTreeMap<NaiveDate, u32>) -> Self {
+        let mut days: BTreeMap<(u32, u32), DayOfYear> = BTreeMap::new();
+        for (date, value) in values {
+            days.entry((date.month(), date.day()))
+                .or_default()
+                .values
+                .push(*value);
+        }
+        for day in days.values_mut() {
+            day.values.sort_unstable();
+        }
+        Climatology { days }
+    }
+
+    pub fn day(&self, date: &NaiveDate) -> Option<&DayOfYear> {
+        self.days.get(&(date.month(), date.day()))
+    }
+
+    /// `value` as a percent of the mean of its day of the year.
+    pub fn percent_of_average(&self, date: &NaiveDate, value: u32) -> Option<f64> {
+        let mean = self.day(date)?.mean()?;
+        if mean == 0.0 {
+            return None;
+        }
+        Some(value as f64 / mean * 100.0)
+    }
+
+    /// The value of a series on `date`, e.g. today, as a percent of
+    /// average; `None` when the series does not reach `date`.
+    pub fn percent_of_average_on(
+        &self,
+        values: &BTreeMap<NaiveDate, u32>,
+        date: &NaiveDate,
+    ) -> Option<f64> {
+        self.percent_of_average(date, *values.get(date)?)
+    }
+}
+
+/// The climatology of every reservoir in the snapshot and of their sum,
+/// see `from_records`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ArchiveClimatology {
+    pub stations: HashMap<String, Climatology>,
+    pub statewide: Climatology,
+    /// Records that could not be read as an observation.
+    pub skipped_rows: usize,
+}
+
+impl ArchiveClimatology {
+    /// Builds the climatology from the daily storage rows of snapshot
+    /// records, as `Observation::get_all_records` returns them. Rows that
+    /// are not daily storage recordings, or do not parse, are left out;
+    /// the ones that do not parse are counted in `skipped_rows`.
+    ///
+    /// The statewide sum of a day some reservoirs did not report is scaled
+    /// up by the share of capacity that did, so that a missing reservoir
+    /// does not look like a drop in storage. Reservoirs without a capacity
+    /// in `capacity.csv` are left out of it.
+    pub fn from_records(records: &[StringRecord]) -> Self {
+        let storage = Sensor::storage().number;
+        let mut station_values: HashMap<String, BTreeMap<NaiveDate, u32>> = HashMap::new();
+        let mut skipped_rows = 0;
+        for record in records {
+            let observation = match Observation::try_from(record.clone()) {
+                Ok(observation) => observation,
+                Err(_) => {
+                    skipped_rows += 1;
+                    continue;
+                }
+            };
+            let value = match observation.value {
+                DataRecording::Recording(value) => value,
+                _ => continue,
+            };
+            if observation.duration != Duration::Daily || observation.sensor.number != storage {
+                continue;
+            }
+            let date = observation.date_observation.date();
+            station_values
+                .entry(observation.station_id)
+                .or_default()
+                .insert(date, value);
+        }
+        let statewide_values = statewide_values(&station_values, station_values.keys());
+        ArchiveClimatology {
+            stations: station_values
+                .iter()
+                .map(|(station_id, values)| (station_id.clone(), Climatology::from_values(values)))
+                .collect(),
+            statewide: Climatology::from_values(&statewide_values),
+            skipped_rows,
+        }
+    }
+
+    /// The climatology of the current snapshot, see
+    /// `Observation::get_all_records`.
+    pub fn from_archive() -> Self {
+        ArchiveClimatology::from_records(Observation::get_all_records().as_slice())
+    }
+
+    /// Statewide storage on `date`, fetched from `source`, as a percent of
+    /// average. The reservoirs of the climatology that reported are scaled
+    /// up by their share of its capacity, as in `from_records`; `None` when
+    /// none of them reported that day.
+    pub async fn percent_of_average_today(
+        &self,
+        source: &dyn CdecSource,
+        date: &NaiveDate,
+    ) -> Option<f64> {
+        let reservoir_observations =
+            Observation::get_all_reservoirs_observations_by_dates(source, date, date).await;
+        let station_values: HashMap<String, BTreeMap<NaiveDate, u32>> = reservoir_observations
+            .station_values()
+            .into_iter()
+            .filter(|(station_id, _)| self.stations.contains_key(station_id))
+            .collect();
+        let statewide_values = statewide_values(&station_values, self.stations.keys());
+        self.statewide
+            .percent_of_average_on(&statewide_values, date)
+    }
+}
+
+// storage summed by day, scaled up by the share of the capacity of
+// `stations` that reported so that a missing reservoir does not look like
+// a drop in storage; reservoirs without a capacity in `capacity.csv` are
+// left out
+fn statewide_values<'a>(
+    station_values: &HashMap<String, BTreeMap<NaiveDate, u32>>,
+    stations: impl Iterator<Item = &'a String>,
+) -> BTreeMap<NaiveDate, u32> {
+    let capacities: HashMap<String, u64> = Reservoir::get_reservoir_vector()
+        .into_iter()
+        .filter(|reservoir| reservoir.capacity > 0)
+        .map(|reservoir| (reservoir.station_id, reservoir.capacity as u64))
+        .collect();
+    let total_capacity: u64 = stations
+        .filter_map(|station_id| capacities.get(station_id))
+        .sum();
+    // (storage, capacity) of the reservoirs that reported each day
+    let mut reported: BTreeMap<NaiveDate, (u64, u64)> = BTreeMap::new();
+    for (station_id, values) in station_values.iter() {
+        let capacity = match capacities.get(station_id) {
+            Some(capacity) => *capacity,
+            None => continue,
+        };
+        for (date, value) in values {
+            let day = reported.entry(*date).or_insert((0, 0));
+            day.0 += *value as u64;
+            day.1 += capacity;
+        }
+    }
+    reported
+        .into_iter()
+        .map(|(date, (storage, capacity))| {
+            let scaled = storage as f64 * total_capacity as f64 / capacity as f64;
+            (date, scaled.round() as u32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ArchiveClimatology, Climatology, DayOfYear};
+    use crate::source::FixtureSource;
+    use chrono::NaiveDate;
+    use csv::{ReaderBuilder, StringRecord};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_day_of_year() {
+        let day = DayOfYear {
+            values: vec![10, 20, 30, 40],
+        };
+        assert_eq!(day.mean(), Some(25.0));
+        assert_eq!(day.median(), Some(25.0));
+        assert_eq!(day.percentile(0.0), Some(10.0));
+        assert_eq!(day.percentile(100.0), Some(40.0));
+        assert_eq!(day.percentile(10.0), Some(13.0));
+        assert_eq!(DayOfYear::default().mean(), None);
+    }
+
+    #[test]
+    fn test_climatology() {
+        let values: BTreeMap<NaiveDate, u32> = (2000..2010)
+            .flat_map(|year| {
+                vec![
+                    (
+                        NaiveDate::from_ymd_opt(year, 3, 1).unwrap(),
+                        100 + year as u32 % 10,
+                    ),
+                    (NaiveDate::from_ymd_opt(year, 3, 2).unwrap(), 200),
+                ]
+            })
+            .collect();
+        let climatology = Climatology::from_values(&values);
+        // March 1st lines up across leap years
+        assert_eq!(climatology.days.len(), 2);
+        let today = NaiveDate::from_ymd_opt(2022, 3, 2).unwrap();
+        assert_eq!(climatology.percent_of_average(&today, 150), Some(75.0));
+        let march_first = climatology
+            .day(&NaiveDate::from_ymd_opt(2022, 3, 1).unwrap())
+            .unwrap();
+        assert_eq!(march_first.mean(), Some(104.5));
+        assert_eq!(march_first.median(), Some(104.5));
+        let mut latest = BTreeMap::new();
+        latest.insert(today, 300);
+        assert_eq!(
+            climatology.percent_of_average_on(&latest, &today),
+            Some(150.0)
+        );
+        // a series that stops short of the day says nothing about it
+        let tomorrow = today.succ_opt().unwrap();
+        assert_eq!(climatology.percent_of_average_on(&latest, &tomorrow), None);
+        let leap_day = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(climatology.percent_of_average(&leap_day, 1), None);
+    }
+
+    #[test]
+    fn test_archive_climatology() {
+        let csv = "SHA,D,15,STORAGE,20200101 0000,20200101 0000,100,,AF
+SHA,D,15,STORAGE,20210101 0000,20210101 0000,300,,AF
+SHA,M,15,STORAGE,20210101 0000,20210101 0000,1000,,AF
+ORO,D,15,STORAGE,20200101 0000,20200101 0000,50,,AF
+ORO,D,15,STORAGE,20210101 0000,20210101 0000,---,,AF
+ORO,D,15,STORAGE,2021-01-02,20210102 0000,60,,AF
+";
+        let records: Vec<StringRecord> = ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv.as_bytes())
+            .records()
+            .map(|record| record.unwrap())
+            .collect();
+        let climatology = ArchiveClimatology::from_records(records.as_slice());
+        let new_year = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let sha = climatology.stations["SHA"].day(&new_year).unwrap();
+        assert_eq!(sha.values, vec![100, 300]);
+        assert_eq!(
+            climatology.stations["ORO"].day(&new_year).unwrap().values,
+            vec![50]
+        );
+        // ORO missing in 2021 is made up for by the capacity that reported
+        let (sha, oro): (f64, f64) = (4552000.0, 3537577.0);
+        let scaled = (300.0 * (sha + oro) / sha).round() as u32;
+        let statewide = climatology.statewide.day(&new_year).unwrap();
+        assert_eq!(statewide.values, vec![150, scaled]);
+        assert!(scaled > 500);
+        assert_eq!(climatology.skipped_rows, 1);
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_percent_of_average_today() {
+        let csv = "SHA,D,15,STORAGE,20200101 0000,20200101 0000,100,,AF
+SHA,D,15,STORAGE,20210101 0000,20210101 0000,300,,AF
+";
+        let records: Vec<StringRecord> = ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv.as_bytes())
+            .records()
+            .map(|record| record.unwrap())
+            .collect();
+        let climatology = ArchiveClimatology::from_records(records.as_slice());
+        let mut source = FixtureSource::new();
+        source.insert(
+            "SHA",
+            "D",
+            "STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+SHA,D,15,STORAGE,20220101 0000,20220101 0000,400,,AF
+",
+        );
+        let today = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        assert_eq!(
+            climatology.percent_of_average_today(&source, &today).await,
+            Some(200.0)
+        );
+        // nothing reported the day after
+        let tomorrow = today.succ_opt().unwrap();
+        assert_eq!(
+            climatology
+                .percent_of_average_today(&source, &tomorrow)
+                .await,
+            None
+        );
+    }
+}
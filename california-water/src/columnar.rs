@@ -0,0 +1,577 @@
+//! A compact binary encoding of observations.
+//!
+//! Consecutive rows of the same station, duration and sensor are stored
+//! as a series, one column at a time:
+//!
+//! ```text
+//! "CWOB" version
+//! dictionary:  count, then (length, utf-8) for every station id,
+//!              sensor type and unit
+//! series:      count, then for every series
+//!   station, duration code, sensor number, sensor type, units, rows
+//!   unit       minutes every date delta is a multiple of
+//!   dates      first observation date, then the deltas in units
+//!   recorded   recording date minus observation date, in minutes
+//!   statuses   four bits a row: value, ART, BRT, --- or invalid
+//!   values     the change from the previous value, rows with a value only
+//!   flags      count, then for every row with a data flag or a provenance
+//!              other than measured: rows since the previous one, the
+//!              flag's character (0 for none) and the provenance
+//! ```
+//!
+//! Every integer is a LEB128 varint, signed ones zigzag encoded first, so
+//! a day of a daily series usually costs a few bytes.
+//!
+//! Version 1, which has two bit statuses and no flags, still reads.
+use crate::{
+    error::ColumnarError,
+    observation::{DataFlag, DataRecording, Duration, Observation, Provenance},
+    sensor::Sensor,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+pub const COLUMNAR_MAGIC: [u8; 4] = *b"CWOB";
+pub const COLUMNAR_VERSION: u8 = 2;
+
+const STATUS_RECORDING: u8 = 0;
+const STATUS_ART: u8 = 1;
+const STATUS_BRT: u8 = 2;
+const STATUS_DASH: u8 = 3;
+const STATUS_INVALID: u8 = 4;
+
+// provenances by their number in the flags
+const PROVENANCES: [Provenance; 5] = [
+    Provenance::Measured,
+    Provenance::InterpolatedFromDaily,
+    Provenance::InterpolatedFromMonthly,
+    Provenance::CarriedForward,
+    Provenance::Imputed,
+];
+
+/// Writes observations in the columnar encoding, keeping their order.
+pub fn write<W: Write>(writer: &mut W, observations: &[Observation]) -> Result<(), ColumnarError> {
+    let mut dictionary = Dictionary::default();
+    let series = split_series(observations);
+    let mut body: Vec<u8> = Vec::new();
+    write_varint(&mut body, series.len() as u64);
+    for rows in series {
+        write_series(&mut body, &mut dictionary, rows);
+    }
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&COLUMNAR_MAGIC);
+    header.push(COLUMNAR_VERSION);
+    write_varint(&mut header, dictionary.strings.len() as u64);
+    for string in dictionary.strings.iter() {
+        write_varint(&mut header, string.len() as u64);
+        header.extend_from_slice(string.as_bytes());
+    }
+    writer.write_all(header.as_slice())?;
+    writer.write_all(body.as_slice())?;
+    Ok(())
+}
+
+/// Reads observations written by `write`.
+pub fn read<R: Read>(reader: &mut R) -> Result<Vec<Observation>, ColumnarError> {
+    let mut input: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let mut input = input.as_slice();
+    if input.get(0..4) != Some(&COLUMNAR_MAGIC[..]) {
+        return Err(ColumnarError::Magic);
+    }
+    let version = *input.get(4).ok_or(ColumnarError::Magic)?;
+    if version == 0 || version > COLUMNAR_VERSION {
+        return Err(ColumnarError::Version(version));
+    }
+    input = &input[5..];
+    let strings = read_varint(&mut input)? as usize;
+    let mut dictionary: Vec<String> = Vec::new();
+    for _ in 0..strings {
+        let length = read_varint(&mut input)? as usize;
+        let bytes = take(&mut input, length)?;
+        let string = std::str::from_utf8(bytes)
+            .map_err(|_| ColumnarError::Corrupt(String::from("dictionary is not utf-8")))?;
+        dictionary.push(String::from(string));
+    }
+    let series = read_varint(&mut input)?;
+    let mut observations: Vec<Observation> = Vec::new();
+    for _ in 0..series {
+        read_series(
+            &mut input,
+            dictionary.as_slice(),
+            version,
+            &mut observations,
+        )?;
+    }
+    if !input.is_empty() {
+        return Err(ColumnarError::Corrupt(String::from(
+            "trailing bytes after the last series",
+        )));
+    }
+    Ok(observations)
+}
+
+/// Writes csv records, e.g. the rows of `Observation::get_all_records`, in
+/// the columnar encoding.
+pub fn write_records<W: Write>(
+    writer: &mut W,
+    records: &[StringRecord],
+) -> Result<(), ColumnarError> {
+    let observations = records
+        .iter()
+        .map(|record| Observation::try_from(record.clone()))
+        .collect::<Result<Vec<Observation>, _>>()?;
+    write(writer, observations.as_slice())
+}
+
+/// Reads the columnar encoding back into csv records, the same records
+/// `StringRecord::try_from` makes of each observation.
+pub fn read_records<R: Read>(reader: &mut R) -> Result<Vec<StringRecord>, ColumnarError> {
+    read(reader)?
+        .into_iter()
+        .map(|observation| Ok(StringRecord::try_from(observation)?))
+        .collect()
+}
+
+// the strings of a file, each stored once
+#[derive(Default)]
+struct Dictionary {
+    strings: Vec<String>,
+    indices: HashMap<String, u64>,
+}
+
+impl Dictionary {
+    fn index(&mut self, string: &str) -> u64 {
+        if let Some(index) = self.indices.get(string) {
+            return *index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(String::from(string));
+        self.indices.insert(String::from(string), index);
+        index
+    }
+}
+
+// runs of consecutive observations of the same station, duration and sensor
+fn split_series(observations: &[Observation]) -> Vec<&[Observation]> {
+    let mut series = Vec::new();
+    let mut start = 0;
+    for i in 1..=observations.len() {
+        let ends = i == observations.len() || {
+            let (previous, next) = (&observations[i - 1], &observations[i]);
+            previous.station_id != next.station_id
+                || previous.duration != next.duration
+                || previous.sensor != next.sensor
+        };
+        if ends {
+            series.push(&observations[start..i]);
+            start = i;
+        }
+    }
+    series
+}
+
+fn write_series(output: &mut Vec<u8>, dictionary: &mut Dictionary, rows: &[Observation]) {
+    let first = &rows[0];
+    write_varint(output, dictionary.index(first.station_id.as_str()));
+    output.push(first.duration.code().as_bytes()[0]);
+    write_varint(output, first.sensor.number as u64);
+    write_varint(output, dictionary.index(first.sensor.kind.as_str()));
+    write_varint(output, dictionary.index(first.sensor.units.as_str()));
+    write_varint(output, rows.len() as u64);
+    // dates
+    let minutes: Vec<i64> = rows
+        .iter()
+        .map(|row| minutes_since_epoch(&row.date_observation))
+        .collect();
+    let deltas: Vec<i64> = minutes.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    let unit = deltas
+        .iter()
+        .fold(0, |unit, delta| gcd(unit, delta.abs()))
+        .max(1);
+    write_varint(output, unit as u64);
+    write_varint(output, zigzag(minutes[0]));
+    for delta in deltas {
+        write_varint(output, zigzag(delta / unit));
+    }
+    for (row, observed) in rows.iter().zip(minutes.iter()) {
+        write_varint(
+            output,
+            zigzag(minutes_since_epoch(&row.date_recording) - observed),
+        );
+    }
+    // statuses, two to a byte
+    for chunk in rows.chunks(2) {
+        let mut byte = 0u8;
+        for (i, row) in chunk.iter().enumerate() {
+            let status = match row.value {
+                DataRecording::Recording(_) => STATUS_RECORDING,
+                DataRecording::Art => STATUS_ART,
+                DataRecording::Brt => STATUS_BRT,
+                DataRecording::Dash => STATUS_DASH,
+                DataRecording::Invalid => STATUS_INVALID,
+            };
+            byte |= status << (i * 4);
+        }
+        output.push(byte);
+    }
+    // values
+    let mut previous = 0i64;
+    for row in rows {
+        if let DataRecording::Recording(value) = row.value {
+            write_varint(output, zigzag(value as i64 - previous));
+            previous = value as i64;
+        }
+    }
+    // flags, for the few rows that have one
+    let flagged: Vec<(usize, &Observation)> = rows
+        .iter()
+        .enumerate()
+        .filter(|(_, row)| row.data_flag.is_some() || !row.provenance.is_measured())
+        .collect();
+    write_varint(output, flagged.len() as u64);
+    let mut previous_row = 0;
+    for (i, row) in flagged {
+        write_varint(output, (i - previous_row) as u64);
+        previous_row = i;
+        let flag = row.data_flag.map(|flag| flag.code() as u64).unwrap_or(0);
+        write_varint(output, flag);
+        let provenance = PROVENANCES
+            .iter()
+            .position(|provenance| *provenance == row.provenance)
+            .unwrap();
+        write_varint(output, provenance as u64);
+    }
+}
+
+fn read_series(
+    input: &mut &[u8],
+    dictionary: &[String],
+    version: u8,
+    observations: &mut Vec<Observation>,
+) -> Result<(), ColumnarError> {
+    let lookup = |index: u64| {
+        dictionary
+            .get(index as usize)
+            .ok_or_else(|| ColumnarError::Corrupt(format!("no dictionary entry {}", index)))
+    };
+    let station_id = lookup(read_varint(input)?)?;
+    let code = take(input, 1)?[0] as char;
+    let duration = Duration::try_from(code.to_string().as_str())
+        .map_err(|_| ColumnarError::Corrupt(format!("unknown duration code {:?}", code)))?;
+    let number = read_varint(input)? as u32;
+    let kind = lookup(read_varint(input)?)?;
+    let units = lookup(read_varint(input)?)?;
+    let sensor = Sensor::new(number, kind.as_str(), units.as_str());
+    let rows = read_varint(input)?;
+    if rows == 0 {
+        return Err(ColumnarError::Corrupt(String::from("empty series")));
+    }
+    // every row takes at least a byte for each of its two dates
+    if rows > (input.len() / 2) as u64 {
+        return Err(ColumnarError::Corrupt(format!(
+            "{} rows in {} bytes",
+            rows,
+            input.len()
+        )));
+    }
+    let rows = rows as usize;
+    let date_out_of_range = || ColumnarError::Corrupt(String::from("date out of range"));
+    // dates
+    let unit = read_varint(input)? as i64;
+    let mut minutes: Vec<i64> = Vec::with_capacity(rows);
+    minutes.push(unzigzag(read_varint(input)?));
+    for i in 1..rows {
+        let observed = unzigzag(read_varint(input)?)
+            .checked_mul(unit)
+            .and_then(|step| minutes[i - 1].checked_add(step))
+            .ok_or_else(date_out_of_range)?;
+        minutes.push(observed);
+    }
+    let mut recorded: Vec<i64> = Vec::with_capacity(rows);
+    for observed in minutes.iter() {
+        let delay = unzigzag(read_varint(input)?);
+        recorded.push(observed.checked_add(delay).ok_or_else(date_out_of_range)?);
+    }
+    // statuses two to a byte, four in version 1, then the values of the
+    // rows that have one
+    let (bits, shift) = if version == 1 { (2, 2) } else { (4, 1) };
+    let per_byte = 1 << shift;
+    let statuses = take(input, (rows + per_byte - 1) >> shift)?;
+    let first_row = observations.len();
+    let mut previous = 0i64;
+    for i in 0..rows {
+        let status = (statuses[i / per_byte] >> ((i % per_byte) * bits)) & ((1 << bits) - 1);
+        let value = match status {
+            STATUS_RECORDING => {
+                previous = previous
+                    .checked_add(unzigzag(read_varint(input)?))
+                    .ok_or_else(|| ColumnarError::Corrupt(String::from("value out of range")))?;
+                let value = u32::try_from(previous).map_err(|_| {
+                    ColumnarError::Corrupt(format!("value {} out of range", previous))
+                })?;
+                DataRecording::Recording(value)
+            }
+            STATUS_ART => DataRecording::Art,
+            STATUS_BRT => DataRecording::Brt,
+            STATUS_DASH => DataRecording::Dash,
+            STATUS_INVALID => DataRecording::Invalid,
+            status => return Err(ColumnarError::Corrupt(format!("unknown status {}", status))),
+        };
+        observations.push(Observation {
+            station_id: station_id.clone(),
+            sensor: sensor.clone(),
+            date_observation: from_minutes_since_epoch(minutes[i])?,
+            date_recording: from_minutes_since_epoch(recorded[i])?,
+            value,
+            duration,
+            data_flag: None,
+            provenance: Provenance::Measured,
+        });
+    }
+    if version == 1 {
+        return Ok(());
+    }
+    let flagged = read_varint(input)?;
+    let mut row = 0;
+    for _ in 0..flagged {
+        let skipped = read_varint(input)?;
+        if skipped >= (rows - row) as u64 {
+            return Err(ColumnarError::Corrupt(String::from(
+                "flag past the last row",
+            )));
+        }
+        row += skipped as usize;
+        let observation = &mut observations[first_row + row];
+        observation.data_flag = match read_varint(input)? {
+            0 => None,
+            code => {
+                let code = u32::try_from(code)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| ColumnarError::Corrupt(format!("data flag {}", code)))?;
+                Some(DataFlag::from_code(code))
+            }
+        };
+        let provenance = read_varint(input)?;
+        observation.provenance = *PROVENANCES
+            .get(provenance as usize)
+            .ok_or_else(|| ColumnarError::Corrupt(format!("provenance {}", provenance)))?;
+    }
+    Ok(())
+}
+
+fn epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1970, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+fn minutes_since_epoch(date: &NaiveDateTime) -> i64 {
+    (*date - epoch()).num_minutes()
+}
+
+fn from_minutes_since_epoch(minutes: i64) -> Result<NaiveDateTime, ColumnarError> {
+    // far past any date chrono can hold, and short of overflowing a Duration
+    if minutes.unsigned_abs() > 1 << 40 {
+        return Err(ColumnarError::Corrupt(format!(
+            "date {} out of range",
+            minutes
+        )));
+    }
+    epoch()
+        .checked_add_signed(chrono::Duration::minutes(minutes))
+        .ok_or_else(|| ColumnarError::Corrupt(format!("date {} out of range", minutes)))
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64, ColumnarError> {
+    let mut value = 0u64;
+    for (i, byte) in input.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(ColumnarError::Corrupt(String::from("truncated integer")))
+}
+
+fn take<'a>(input: &mut &'a [u8], length: usize) -> Result<&'a [u8], ColumnarError> {
+    if input.len() < length {
+        return Err(ColumnarError::Corrupt(String::from("truncated")));
+    }
+    let (taken, rest) = input.split_at(length);
+    *input = rest;
+    Ok(taken)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        read, read_records, unzigzag, write, write_records, write_varint, zigzag, STATUS_DASH,
+    };
+    use crate::{
+        compression::decompress_tar_file_to_csv_string,
+        error::ColumnarError,
+        observation::{DataFlag, DataRecording, Observation, Provenance},
+    };
+    use csv::{ReaderBuilder, StringRecord};
+    static BLOCKS_TEST_OBJECT: &[u8] = include_bytes!("../test-fixtures/blocks.tar.xz");
+
+    const MIXED_CSV: &str = "SHA,D,15,STORAGE,19850101 0000,19850101 0000,1543200,,AF
+SHA,D,15,STORAGE,19850102 0000,19850102 0000,---,,AF
+SHA,D,15,STORAGE,19850104 0000,19850104 0000,1543000,,AF
+SHA,D,15,STORAGE,19850105 0000,19850105 0000,1542900,e interpolated-daily,AF
+SHA,D,15,STORAGE,19850106 0000,19850106 0000,,v,AF
+SHA,D,15,STORAGE,19850107 0000,19850107 0000,1542800,carried-forward,AF
+SHA,M,15,STORAGE,19850201 0000,19850201 0000,1500000,,AF
+VIL,H,6,RES ELE,20220215 0700,20220215 0600,ART,,FEET
+VIL,H,6,RES ELE,20220215 0800,20220215 0700,BRT,,FEET
+SHA,D,15,STORAGE,19850103 0000,19850103 0000,0,,AF
+";
+
+    fn records(csv: &[u8]) -> Vec<StringRecord> {
+        ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv)
+            .records()
+            .map(|record| record.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_zigzag() {
+        for value in [0i64, 1, -1, 1440, -1440, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert_eq!(zigzag(-1), 1);
+    }
+
+    #[test]
+    fn test_round_trip_records() {
+        let records = records(MIXED_CSV.as_bytes());
+        let mut bytes: Vec<u8> = Vec::new();
+        write_records(&mut bytes, records.as_slice()).unwrap();
+        assert_eq!(read_records(&mut bytes.as_slice()).unwrap(), records);
+        let observations = read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(observations[3].data_flag, Some(DataFlag::Estimated));
+        assert_eq!(
+            observations[3].provenance,
+            Provenance::InterpolatedFromDaily
+        );
+        assert_eq!(observations[4].value, DataRecording::Invalid);
+        assert_eq!(observations[4].data_flag, Some(DataFlag::OutOfRange));
+        assert_eq!(observations[5].data_flag, None);
+        assert_eq!(observations[5].provenance, Provenance::CarriedForward);
+    }
+
+    #[test]
+    fn test_read_version_1() {
+        let mut bytes: Vec<u8> = b"CWOB\x01\x03\x03SHA\x07STORAGE\x02AF".to_vec();
+        // one daily series of a value and a ---, from 1970-01-01
+        bytes.extend_from_slice(&[1, 0, b'D', 15, 1, 2, 2, 0xa0, 0x0b, 0, 2, 0, 0]);
+        bytes.extend_from_slice(&[STATUS_DASH << 2, 0xc8, 0x01]);
+        let observations = read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[0].value, DataRecording::Recording(100));
+        assert_eq!(observations[1].value, DataRecording::Dash);
+        assert_eq!(
+            observations[1].date_observation.to_string(),
+            "1970-01-02 00:00:00"
+        );
+        assert_eq!(observations[1].provenance, Provenance::Measured);
+    }
+
+    #[test]
+    fn test_read_corrupt_series() {
+        let header = b"CWOB\x02\x03\x03SHA\x07STORAGE\x02AF\x01\x00D\x0f\x01\x02";
+        let corrupt = |series: &[u64]| {
+            let mut bytes: Vec<u8> = header.to_vec();
+            for value in series {
+                write_varint(&mut bytes, *value);
+            }
+            bytes.extend_from_slice(&[0, 0]);
+            read(&mut bytes.as_slice())
+        };
+        // far more rows than there are bytes
+        assert!(matches!(
+            corrupt(&[u64::MAX, 1440, 0]),
+            Err(ColumnarError::Corrupt(_))
+        ));
+        // a step between dates that overflows once scaled by the unit
+        assert!(matches!(
+            corrupt(&[2, 1440, 0, zigzag(i64::MAX), 0, 0]),
+            Err(ColumnarError::Corrupt(_))
+        ));
+        // a recording date past the end of an i64
+        assert!(matches!(
+            corrupt(&[2, 1, zigzag(i64::MAX - 1), 2, 0, zigzag(5)]),
+            Err(ColumnarError::Corrupt(_))
+        ));
+        // a first date of i64::MIN, which has no absolute value
+        assert!(matches!(
+            corrupt(&[1, 1, u64::MAX, 0]),
+            Err(ColumnarError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_snapshot() {
+        let csv = decompress_tar_file_to_csv_string(BLOCKS_TEST_OBJECT);
+        let records = records(csv.as_slice());
+        let observations: Vec<Observation> = records
+            .iter()
+            .map(|record| Observation::try_from(record.clone()).unwrap())
+            .collect();
+        let mut bytes: Vec<u8> = Vec::new();
+        write(&mut bytes, observations.as_slice()).unwrap();
+        // a few bytes a day instead of a csv row
+        assert!(bytes.len() * 10 < csv.len());
+        assert_eq!(read(&mut bytes.as_slice()).unwrap(), observations);
+        // the records an observation becomes, e.g. CDEC's " " data flag is
+        // written as ""
+        let canonical: Vec<StringRecord> = observations
+            .into_iter()
+            .map(|observation| StringRecord::try_from(observation).unwrap())
+            .collect();
+        assert_eq!(read_records(&mut bytes.as_slice()).unwrap(), canonical);
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(
+            read(&mut bytes.as_slice()),
+            Err(ColumnarError::Corrupt(_))
+        ));
+        assert!(matches!(
+            read(&mut &b"SHA,D,15"[..]),
+            Err(ColumnarError::Magic)
+        ));
+    }
+}
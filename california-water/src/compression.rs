@@ -1,34 +1,237 @@
-use lzma_rs::xz_decompress;
-use std::io::{BufReader, Read};
+use crate::{bundle::MANIFEST_NAME, error::BundleError};
+use lzma_rs::{lzma2_decompress, xz_decompress};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    io::{BufReader, Read},
+};
 use tar::Archive;
 pub static TAR_OBJECT: &[u8] = include_bytes!("../obj/output.tar.lzma");
 
+const XZ_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const XZ_FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+const XZ_LZMA2_FILTER: u64 = 0x21;
+const XZ_CHECK_NONE: u8 = 0x00;
+const XZ_CHECK_CRC32: u8 = 0x01;
+const XZ_CHECK_CRC64: u8 = 0x04;
+const XZ_CHECK_SHA256: u8 = 0x0a;
+
+/// The csv rows of a snapshot: every entry of the xz compressed tar but the
+/// bundle manifest, one after the other.
 pub fn decompress_tar_file_to_csv_string(input: &[u8]) -> Vec<u8> {
+    let entries = xz_tar_entries(input).expect("reading csv file failed");
+    let mut buf: Vec<u8> = Vec::new();
+    for (name, mut contents) in entries {
+        if name == MANIFEST_NAME {
+            continue;
+        }
+        if !buf.is_empty() && !buf.ends_with(b"\n") {
+            buf.push(b'\n');
+        }
+        buf.append(&mut contents);
+    }
+    buf
+}
+
+/// The (name, contents) of every file in an xz compressed tar, in order.
+pub fn xz_tar_entries(input: &[u8]) -> Result<Vec<(String, Vec<u8>)>, BundleError> {
     let mut tar_object_buffer = BufReader::new(input);
     let mut decompress_output: Vec<u8> = Vec::new();
-    xz_decompress(&mut tar_object_buffer, &mut decompress_output).unwrap();
+    xz_decompress(&mut tar_object_buffer, &mut decompress_output).map_err(BundleError::Xz)?;
     // read decompress_output with archive
     let mut tar_file_from_decompress_output = Archive::new(decompress_output.as_slice());
-    let mut tar_file_enumerator = tar_file_from_decompress_output
-        .entries()
-        .unwrap()
-        .enumerate();
-    let mut buf: Vec<u8> = Vec::new();
-    if let Some((_i, csv_file_result)) = tar_file_enumerator.next() {
-        let mut csv_file = csv_file_result.unwrap();
-        if csv_file.read_to_end(&mut buf).is_err() {
-            panic!("reading csv file failed");
+    let mut entries = Vec::new();
+    for file in tar_file_from_decompress_output.entries()? {
+        let mut file = file?;
+        let name = file.path()?.to_string_lossy().to_string();
+        let mut contents: Vec<u8> = Vec::new();
+        file.read_to_end(&mut contents)?;
+        entries.push((name, contents));
+    }
+    Ok(entries)
+}
+
+/// Decompresses an xz file one block at a time.
+///
+/// `xz_decompress` only hands its output over once a whole block has been
+/// decoded, and a file written by a single threaded `xz` is one block, so
+/// the whole archive ends up in memory. Reading the block index lets each
+/// block be decoded on its own instead; a file written with a block size,
+/// e.g. `xz --block-size=1MiB`, then never needs more than one block in
+/// memory at once. Every block is checked against the CRC32, CRC64 or
+/// SHA-256 the file was written with.
+#[derive(Debug, Clone)]
+pub struct XzBlocks<'a> {
+    input: Cow<'a, [u8]>,
+    check: u8,
+    // (offset, unpadded size) of every block not decoded yet
+    blocks: std::vec::IntoIter<(usize, usize)>,
+}
+
+impl<'a> XzBlocks<'a> {
+    /// Reads the stream header, footer and index of a single stream xz
+    /// file, borrowed or owned.
+    pub fn new<I: Into<Cow<'a, [u8]>>>(input: I) -> Result<Self, BundleError> {
+        let input = input.into();
+        let (check, blocks) = XzBlocks::read_index(&input)?;
+        Ok(XzBlocks {
+            input,
+            check,
+            blocks: blocks.into_iter(),
+        })
+    }
+
+    // the check of the stream and the (offset, unpadded size) of every
+    // block
+    fn read_index(input: &[u8]) -> Result<(u8, Vec<(usize, usize)>), BundleError> {
+        if input.len() < 24 || input[0..6] != XZ_MAGIC {
+            return Err(BundleError::Container(String::from("not an xz file")));
+        }
+        let check = input[7] & 0x0f;
+        if check_size(check).is_none() {
+            return Err(BundleError::Container(format!(
+                "unsupported check {:#04x}",
+                check
+            )));
+        }
+        // stream padding is zeros in multiples of four after the footer
+        let mut end = input.len();
+        while end >= 4 && input[end - 4..end] == [0, 0, 0, 0] {
+            end -= 4;
         }
+        if end < 24 || input[end - 2..end] != XZ_FOOTER_MAGIC {
+            return Err(BundleError::Container(String::from(
+                "missing stream footer",
+            )));
+        }
+        let backward_size = u32::from_le_bytes([
+            input[end - 8],
+            input[end - 7],
+            input[end - 6],
+            input[end - 5],
+        ]) as usize;
+        let index_size = (backward_size + 1) * 4;
+        let index_start = (end - 12)
+            .checked_sub(index_size)
+            .filter(|start| *start >= 12 && input[*start] == 0)
+            .ok_or_else(|| BundleError::Container(String::from("bad index")))?;
+        let mut index = &input[index_start + 1..end - 12];
+        let records = read_varint(&mut index)?;
+        let mut blocks = Vec::new();
+        let mut offset = 12;
+        for _ in 0..records {
+            let unpadded_size = read_varint(&mut index)? as usize;
+            // the uncompressed size is not needed
+            read_varint(&mut index)?;
+            blocks.push((offset, unpadded_size));
+            // blocks are padded to four bytes
+            offset += (unpadded_size + 3) & !3;
+        }
+        if offset != index_start {
+            // more than one stream, or blocks the index does not list
+            return Err(BundleError::Container(String::from(
+                "index does not match the blocks",
+            )));
+        }
+        Ok((check, blocks))
+    }
+
+    fn decode_block(&self, offset: usize, unpadded_size: usize) -> Result<Vec<u8>, BundleError> {
+        let bad_block = || BundleError::Container(format!("bad block at {}", offset));
+        let block = self
+            .input
+            .get(offset..offset + unpadded_size)
+            .ok_or_else(bad_block)?;
+        let header_size = (*block.first().ok_or_else(bad_block)? as usize + 1) * 4;
+        let mut header = block.get(2..header_size - 4).ok_or_else(bad_block)?;
+        let flags = block[1];
+        if flags & 0x03 != 0 {
+            return Err(BundleError::Container(String::from(
+                "only lzma2 blocks without other filters are supported",
+            )));
+        }
+        if flags & 0x40 != 0 {
+            read_varint(&mut header)?;
+        }
+        if flags & 0x80 != 0 {
+            read_varint(&mut header)?;
+        }
+        if read_varint(&mut header)? != XZ_LZMA2_FILTER {
+            return Err(BundleError::Container(String::from(
+                "only lzma2 blocks without other filters are supported",
+            )));
+        }
+        let check_size = check_size(self.check).unwrap_or(0);
+        let compressed_end = unpadded_size
+            .checked_sub(check_size)
+            .filter(|end| *end >= header_size)
+            .ok_or_else(bad_block)?;
+        let mut compressed = &block[header_size..compressed_end];
+        let mut output: Vec<u8> = Vec::new();
+        lzma2_decompress(&mut compressed, &mut output).map_err(BundleError::Xz)?;
+        // the check follows the padding, which the unpadded size leaves out
+        let check_start = (compressed_end + 3) & !3;
+        let expected = self
+            .input
+            .get(offset + check_start..offset + check_start + check_size)
+            .ok_or_else(bad_block)?;
+        let found = match self.check {
+            XZ_CHECK_CRC32 => crc::crc32::checksum_ieee(&output).to_le_bytes().to_vec(),
+            XZ_CHECK_CRC64 => crc::crc64::checksum_ecma(&output).to_le_bytes().to_vec(),
+            XZ_CHECK_SHA256 => Sha256::digest(&output).to_vec(),
+            _ => Vec::new(),
+        };
+        if found != expected {
+            return Err(BundleError::Container(format!(
+                "block at {} fails its check",
+                offset
+            )));
+        }
+        Ok(output)
     }
-    buf
+}
+
+impl<'a> Iterator for XzBlocks<'a> {
+    type Item = Result<Vec<u8>, BundleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (offset, unpadded_size) = self.blocks.next()?;
+        Some(self.decode_block(offset, unpadded_size))
+    }
+}
+
+// the bytes of the checks that are verified, `None` for the others
+fn check_size(check: u8) -> Option<usize> {
+    match check {
+        XZ_CHECK_NONE => Some(0),
+        XZ_CHECK_CRC32 => Some(4),
+        XZ_CHECK_CRC64 => Some(8),
+        XZ_CHECK_SHA256 => Some(32),
+        _ => None,
+    }
+}
+
+// xz's variable length integers, seven bits a byte, least significant first
+fn read_varint(input: &mut &[u8]) -> Result<u64, BundleError> {
+    let mut value = 0u64;
+    for (i, byte) in input.iter().enumerate().take(9) {
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(BundleError::Container(String::from("bad integer")))
 }
 
 #[cfg(test)]
 mod test {
-    use super::decompress_tar_file_to_csv_string;
+    use super::{decompress_tar_file_to_csv_string, XzBlocks};
+    use crate::error::BundleError;
     use hex_literal::hex;
     use sha3::{Digest, Sha3_384};
     pub static TAR_TEST_OBJECT: &[u8] = include_bytes!("../test-fixtures/output.tar.lzma");
+    static BLOCKS_TEST_OBJECT: &[u8] = include_bytes!("../test-fixtures/blocks.tar.xz");
     #[test]
     fn test_decompress_tar_file_to_csv_string() {
         let output = decompress_tar_file_to_csv_string(TAR_TEST_OBJECT);
@@ -40,4 +243,33 @@ mod test {
         let result = hasher.finalize();
         assert_eq!(result[..], hex!("35f323d919c0c9ef3bd00f2421c28195506eb67cc971e7a9e3529742337ffdff3636ce839035fa273d90301245fff39d"));
     }
+
+    #[test]
+    fn test_xz_blocks() {
+        let mut whole: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut &BLOCKS_TEST_OBJECT[..], &mut whole).unwrap();
+        let blocks: Vec<Vec<u8>> = XzBlocks::new(BLOCKS_TEST_OBJECT)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(blocks.len(), 7);
+        assert!(blocks.iter().all(|block| block.len() <= 8192));
+        assert_eq!(blocks.concat(), whole);
+        assert!(XzBlocks::new(&b"not an xz file at all, really"[..]).is_err());
+    }
+
+    #[test]
+    fn test_xz_blocks_check() {
+        let (_, blocks) = XzBlocks::read_index(BLOCKS_TEST_OBJECT).unwrap();
+        let (offset, unpadded_size) = blocks[0];
+        // the last byte of the first block's check, after its padding
+        let mut corrupt = BLOCKS_TEST_OBJECT.to_vec();
+        corrupt[offset + ((unpadded_size + 3) & !3) - 1] ^= 0xff;
+        let mut decoded = XzBlocks::new(corrupt).unwrap();
+        assert!(matches!(
+            decoded.next(),
+            Some(Err(BundleError::Container(_)))
+        ));
+        assert!(decoded.next().unwrap().is_ok());
+    }
 }
@@ -0,0 +1,384 @@
+use crate::source::{CdecRequest, SourceError};
+use std::{fmt, io, num::ParseIntError, path::PathBuf};
+
+/// Why a single csv row could not be turned into an `Observation`
+/// (or the other way around).
+#[derive(Debug)]
+pub enum RowError {
+    Length(usize),
+    Duration(String),
+    SensorNumber(ParseIntError),
+    Date(chrono::ParseError),
+    Utf8(csv::Utf8Error),
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::Length(len) => write!(f, "expected 9 columns, found {}", len),
+            RowError::Duration(code) => write!(f, "unknown duration code {:?}", code),
+            RowError::SensorNumber(e) => write!(f, "bad sensor number: {}", e),
+            RowError::Date(e) => write!(f, "bad date: {}", e),
+            RowError::Utf8(e) => write!(f, "invalid utf-8: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RowError::SensorNumber(e) => Some(e),
+            RowError::Date(e) => Some(e),
+            RowError::Utf8(e) => Some(e),
+            RowError::Length(..) | RowError::Duration(..) => None,
+        }
+    }
+}
+
+/// Why a cache file could not be read or written.
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    Csv(csv::Error),
+    Row { line: Option<u64>, source: RowError },
+    Coverage(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Io(e) => write!(f, "{}", e),
+            CacheError::Csv(e) => write!(f, "{}", e),
+            CacheError::Row {
+                line: Some(line),
+                source,
+            } => write!(f, "line {}: {}", line, source),
+            CacheError::Row { line: None, source } => write!(f, "{}", source),
+            CacheError::Coverage(contents) => write!(f, "bad coverage {:?}", contents),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CacheError::Io(e) => Some(e),
+            CacheError::Csv(e) => Some(e),
+            CacheError::Row { source, .. } => Some(source),
+            CacheError::Coverage(..) => None,
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError::Io(e)
+    }
+}
+
+impl From<csv::Error> for CacheError {
+    fn from(e: csv::Error) -> Self {
+        CacheError::Csv(e)
+    }
+}
+
+/// Why a dataset bundle could not be read, checked or written.
+#[derive(Debug)]
+pub enum BundleError {
+    Io(io::Error),
+    Xz(lzma_rs::error::Error),
+    /// The xz container around the compressed blocks could not be read.
+    Container(String),
+    Manifest(serde_json::Error),
+    Version(u32),
+    /// A csv row of the bundle is not an observation.
+    Row {
+        name: String,
+        line: u64,
+        source: RowError,
+    },
+    MissingEntry(String),
+    UnlistedEntry(String),
+    Checksum {
+        name: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for BundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BundleError::Io(e) => write!(f, "{}", e),
+            BundleError::Xz(e) => write!(f, "{}", e),
+            BundleError::Container(reason) => write!(f, "bad xz file: {}", reason),
+            BundleError::Manifest(e) => write!(f, "bad manifest: {}", e),
+            BundleError::Version(version) => {
+                write!(f, "bundle version {} is newer than this reader", version)
+            }
+            BundleError::Row { name, line, source } => {
+                write!(f, "{} line {}: {}", name, line, source)
+            }
+            BundleError::MissingEntry(name) => {
+                write!(f, "{} is in the manifest but not the bundle", name)
+            }
+            BundleError::UnlistedEntry(name) => {
+                write!(f, "{} is in the bundle but not the manifest", name)
+            }
+            BundleError::Checksum {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has sha256 {}, the manifest expects {}",
+                name, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BundleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BundleError::Io(e) => Some(e),
+            BundleError::Xz(e) => Some(e),
+            BundleError::Manifest(e) => Some(e),
+            BundleError::Row { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BundleError {
+    fn from(e: io::Error) -> Self {
+        BundleError::Io(e)
+    }
+}
+
+/// Why observations could not be written to or read from the columnar
+/// encoding.
+#[derive(Debug)]
+pub enum ColumnarError {
+    Io(io::Error),
+    /// The input does not start with the columnar magic bytes.
+    Magic,
+    Version(u8),
+    Corrupt(String),
+    Row(RowError),
+}
+
+impl fmt::Display for ColumnarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnarError::Io(e) => write!(f, "{}", e),
+            ColumnarError::Magic => write!(f, "not columnar observations"),
+            ColumnarError::Version(version) => {
+                write!(f, "columnar version {} is newer than this reader", version)
+            }
+            ColumnarError::Corrupt(reason) => {
+                write!(f, "corrupt columnar observations: {}", reason)
+            }
+            ColumnarError::Row(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ColumnarError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ColumnarError::Io(e) => Some(e),
+            ColumnarError::Row(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ColumnarError {
+    fn from(e: io::Error) -> Self {
+        ColumnarError::Io(e)
+    }
+}
+
+impl From<RowError> for ColumnarError {
+    fn from(e: RowError) -> Self {
+        ColumnarError::Row(e)
+    }
+}
+
+/// Everything that can go wrong while fetching and parsing observations.
+///
+/// The failing request (station, sensor, duration and date range) is
+/// attached whenever it is known so that one bad station out of the
+/// whole state can be identified.
+#[derive(Debug)]
+pub enum ObservationError {
+    Request {
+        request: Box<CdecRequest>,
+        source: SourceError,
+    },
+    Csv {
+        request: Option<Box<CdecRequest>>,
+        line: Option<u64>,
+        source: csv::Error,
+    },
+    Row {
+        request: Option<Box<CdecRequest>>,
+        line: Option<u64>,
+        row: String,
+        source: RowError,
+    },
+    Cache {
+        path: PathBuf,
+        source: CacheError,
+    },
+}
+
+impl ObservationError {
+    /// Attaches the request that produced the csv body, unless one is
+    /// already known.
+    pub fn with_request(self, request: &CdecRequest) -> Self {
+        match self {
+            ObservationError::Csv {
+                request: None,
+                line,
+                source,
+            } => ObservationError::Csv {
+                request: Some(Box::new(request.clone())),
+                line,
+                source,
+            },
+            ObservationError::Row {
+                request: None,
+                line,
+                row,
+                source,
+            } => ObservationError::Row {
+                request: Some(Box::new(request.clone())),
+                line,
+                row,
+                source,
+            },
+            e => e,
+        }
+    }
+
+    pub fn request(&self) -> Option<&CdecRequest> {
+        match self {
+            ObservationError::Request { request, .. } => Some(request),
+            ObservationError::Csv { request, .. } => request.as_deref(),
+            ObservationError::Row { request, .. } => request.as_deref(),
+            ObservationError::Cache { .. } => None,
+        }
+    }
+
+    pub fn station_id(&self) -> Option<&str> {
+        self.request().map(|request| request.station_id.as_str())
+    }
+
+    /// The http status CDEC answered with, if the request got that far.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ObservationError::Request { source, .. } => source.status(),
+            _ => None,
+        }
+    }
+
+    /// The csv line number of the offending row.
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            ObservationError::Request { .. } => None,
+            ObservationError::Csv { line, .. } => *line,
+            ObservationError::Row { line, .. } => *line,
+            ObservationError::Cache { source, .. } => match source {
+                CacheError::Row { line, .. } => *line,
+                _ => None,
+            },
+        }
+    }
+}
+
+fn describe(request: &CdecRequest) -> String {
+    format!(
+        "{} sensor {} ({}) from {} to {}",
+        request.station_id,
+        request.sensor_number,
+        request.dur_code,
+        request.start_date,
+        request.end_date
+    )
+}
+
+impl fmt::Display for ObservationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = match self.request() {
+            Some(request) => describe(request),
+            None => String::from("csv body"),
+        };
+        let line = match self.line() {
+            Some(line) => format!(" line {}", line),
+            None => String::new(),
+        };
+        match self {
+            ObservationError::Request { source, .. } => {
+                write!(f, "request for {} failed: {}", context, source)
+            }
+            ObservationError::Csv { source, .. } => {
+                write!(f, "reading {}{} failed: {}", context, line, source)
+            }
+            ObservationError::Row { row, source, .. } => {
+                write!(
+                    f,
+                    "parsing {}{} failed: {} in {:?}",
+                    context, line, source, row
+                )
+            }
+            ObservationError::Cache { path, source } => {
+                write!(f, "cache file {} failed: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObservationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObservationError::Request { source, .. } => Some(source),
+            ObservationError::Csv { source, .. } => Some(source),
+            ObservationError::Row { source, .. } => Some(source),
+            ObservationError::Cache { source, .. } => Some(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ObservationError, RowError};
+    use crate::source::CdecRequest;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_with_request_and_display() {
+        let request = CdecRequest::new(
+            "VIL",
+            15,
+            "D",
+            &NaiveDate::from_ymd_opt(2022, 2, 15).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 28).unwrap(),
+        );
+        let error = ObservationError::Row {
+            request: None,
+            line: Some(3),
+            row: String::from("VIL,X,15"),
+            source: RowError::Length(3),
+        };
+        assert_eq!(error.station_id(), None);
+        let error = error.with_request(&request);
+        assert_eq!(error.station_id(), Some("VIL"));
+        assert_eq!(error.line(), Some(3));
+        assert_eq!(
+            error.to_string(),
+            "parsing VIL sensor 15 (D) from 2022-02-15 to 2022-02-28 line 3 failed: expected 9 columns, found 3 in \"VIL,X,15\""
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Observations as Arrow record batches, and as Parquet files with the
+//! `parquet` feature, so they load into pandas or DuckDB with typed dates.
+use crate::observation::{DataRecording, Observation, SensorValue};
+use arrow::{
+    array::{ArrayRef, Float64Array, Int32Array, StringArray, TimestampSecondArray, UInt32Array},
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use chrono::NaiveDateTime;
+use std::sync::Arc;
+
+/// A `SensorValue` with a column type of its own.
+pub trait ArrowValue: SensorValue {
+    fn data_type() -> DataType;
+    fn array(values: Vec<Option<Self>>) -> ArrayRef;
+}
+
+impl ArrowValue for u32 {
+    fn data_type() -> DataType {
+        DataType::UInt32
+    }
+
+    fn array(values: Vec<Option<Self>>) -> ArrayRef {
+        Arc::new(UInt32Array::from(values))
+    }
+}
+
+impl ArrowValue for i32 {
+    fn data_type() -> DataType {
+        DataType::Int32
+    }
+
+    fn array(values: Vec<Option<Self>>) -> ArrayRef {
+        Arc::new(Int32Array::from(values))
+    }
+}
+
+impl ArrowValue for f64 {
+    fn data_type() -> DataType {
+        DataType::Float64
+    }
+
+    fn array(values: Vec<Option<Self>>) -> ArrayRef {
+        Arc::new(Float64Array::from(values))
+    }
+}
+
+/// The columns of an observation record batch, in the order of the csv
+/// columns, then CDEC's data flag and where the value came from. `value`
+/// is null where CDEC reported `ART`, `BRT`, `---` or something that is
+/// not a number, which `status` then holds.
+///
+/// `value` is a `UInt32` of storage, other value types have the column of
+/// their `ArrowValue`.
+pub fn observation_schema() -> SchemaRef {
+    observation_schema_of::<u32>()
+}
+
+fn observation_schema_of<V: ArrowValue>() -> SchemaRef {
+    let timestamp = DataType::Timestamp(TimeUnit::Second, None);
+    Arc::new(Schema::new(vec![
+        Field::new("station_id", DataType::Utf8, false),
+        Field::new("duration", DataType::Utf8, false),
+        Field::new("sensor_number", DataType::UInt32, false),
+        Field::new("sensor_type", DataType::Utf8, false),
+        Field::new("date_recording", timestamp.clone(), false),
+        Field::new("date_observation", timestamp, false),
+        Field::new("value", V::data_type(), true),
+        Field::new("status", DataType::Utf8, true),
+        Field::new("units", DataType::Utf8, false),
+        Field::new("data_flag", DataType::Utf8, true),
+        Field::new("provenance", DataType::Utf8, false),
+    ]))
+}
+
+/// One record batch holding every observation, see `observation_schema`.
+pub fn observations_to_record_batch<V: ArrowValue>(
+    observations: &[Observation<V>],
+) -> Result<RecordBatch, ArrowError> {
+    let strings = |f: fn(&Observation<V>) -> &str| -> ArrayRef {
+        Arc::new(
+            observations
+                .iter()
+                .map(f)
+                .map(Some)
+                .collect::<StringArray>(),
+        )
+    };
+    let timestamps = |f: fn(&Observation<V>) -> &NaiveDateTime| -> ArrayRef {
+        Arc::new(
+            observations
+                .iter()
+                .map(|observation| Some(seconds_since_epoch(f(observation))))
+                .collect::<TimestampSecondArray>(),
+        )
+    };
+    let sensor_numbers: UInt32Array = observations
+        .iter()
+        .map(|observation| Some(observation.sensor.number))
+        .collect();
+    let values = V::array(
+        observations
+            .iter()
+            .map(|observation| match observation.value {
+                DataRecording::Recording(value) => Some(value),
+                _ => None,
+            })
+            .collect(),
+    );
+    let statuses: StringArray = observations
+        .iter()
+        .map(|observation| match observation.value {
+            DataRecording::Recording(_) => None,
+            DataRecording::Art => Some("ART"),
+            DataRecording::Brt => Some("BRT"),
+            DataRecording::Dash => Some("---"),
+            DataRecording::Invalid => Some("invalid"),
+        })
+        .collect();
+    let data_flags: StringArray = observations
+        .iter()
+        .map(|observation| observation.data_flag.map(|flag| flag.code().to_string()))
+        .collect();
+    RecordBatch::try_new(
+        observation_schema_of::<V>(),
+        vec![
+            strings(|observation| observation.station_id.as_str()),
+            strings(|observation| observation.duration.code()),
+            Arc::new(sensor_numbers),
+            strings(|observation| observation.sensor.kind.as_str()),
+            timestamps(|observation| &observation.date_recording),
+            timestamps(|observation| &observation.date_observation),
+            values,
+            Arc::new(statuses),
+            strings(|observation| observation.sensor.units.as_str()),
+            Arc::new(data_flags),
+            strings(|observation| observation.provenance.code()),
+        ],
+    )
+}
+
+/// Writes observations as a snappy compressed Parquet file.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W: std::io::Write + Send, V: ArrowValue>(
+    writer: W,
+    observations: &[Observation<V>],
+) -> Result<(), parquet::errors::ParquetError> {
+    use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+    let batch = observations_to_record_batch(observations)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+fn seconds_since_epoch(date: &NaiveDateTime) -> i64 {
+    let epoch = chrono::NaiveDate::from_ymd_opt(1970, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    (*date - epoch).num_seconds()
+}
+
+#[cfg(test)]
+mod test {
+    use super::observations_to_record_batch;
+    use crate::observation::Observation;
+    use arrow::{
+        array::{Array, Float64Array, StringArray, TimestampSecondArray, UInt32Array},
+        datatypes::DataType,
+    };
+    use csv::StringRecord;
+
+    const CSV: &str = "SHA,D,15,STORAGE,19850101 0000,19850101 0000,1543200,,AF
+SHA,D,15,STORAGE,19850102 0000,19850102 0000,---,e imputed,AF
+VIL,H,6,RES ELE,20220215 0700,20220215 0600,ART,,FEET
+";
+
+    fn observations() -> Vec<Observation> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(CSV.as_bytes())
+            .records()
+            .map(|record: Result<StringRecord, _>| Observation::try_from(record.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_record_batch() {
+        let batch = observations_to_record_batch(observations().as_slice()).unwrap();
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(batch.num_columns(), 11);
+        let stations = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(stations.value(2), "VIL");
+        let observed = batch
+            .column(5)
+            .as_any()
+            .downcast_ref::<TimestampSecondArray>()
+            .unwrap();
+        // 1985-01-01 and 2022-02-15 06:00
+        assert_eq!(observed.value(0), 473385600);
+        assert_eq!(observed.value(2), 1644904800);
+        let values = batch
+            .column(6)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .unwrap();
+        assert_eq!(values.value(0), 1543200);
+        assert!(values.is_null(1));
+        let statuses = batch
+            .column(7)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert!(statuses.is_null(0));
+        assert_eq!(statuses.value(1), "---");
+        assert_eq!(statuses.value(2), "ART");
+        assert_eq!(batch.schema().field(2).data_type(), &DataType::UInt32);
+        let data_flags = batch
+            .column(9)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert!(data_flags.is_null(0));
+        assert_eq!(data_flags.value(1), "e");
+        let provenances = batch
+            .column(10)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(provenances.value(0), "measured");
+        assert_eq!(provenances.value(1), "imputed");
+    }
+
+    #[test]
+    fn test_record_batch_fractional() {
+        let record = StringRecord::from(vec![
+            "PNF",
+            "D",
+            "6",
+            "RES ELE",
+            "20220215 0000",
+            "20220215 0000",
+            "-3.5",
+            "",
+            "FEET",
+        ]);
+        let observations: Vec<Observation<f64>> = vec![Observation::try_from(record).unwrap()];
+        let batch = observations_to_record_batch(observations.as_slice()).unwrap();
+        assert_eq!(batch.schema().field(6).data_type(), &DataType::Float64);
+        let values = batch
+            .column(6)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(values.value(0), -3.5);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_parquet() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        let observations = observations();
+        let mut file = tempfile::tempfile().unwrap();
+        super::write_parquet(&mut file, observations.as_slice()).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<_> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            batches[0],
+            observations_to_record_batch(observations.as_slice()).unwrap()
+        );
+    }
+}
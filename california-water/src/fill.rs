@@ -0,0 +1,97 @@
+//! How full reservoirs are: daily storage held up against capacity.
+use crate::water_year::WaterYear;
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How full one reservoir was on one day.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyFill {
+    pub date: NaiveDate,
+    pub storage: u32,
+    /// `None` for reservoirs without a capacity on record.
+    pub percent_of_capacity: Option<f64>,
+    /// Days since storage last reached capacity, `None` when it has not
+    /// done so yet in the series.
+    pub days_since_full: Option<i64>,
+    /// Acre feet below the highest storage of the water year so far, see
+    /// `WaterYear`.
+    pub drawdown: u32,
+}
+
+/// Storage as a percent of capacity, `None` when the capacity is unknown.
+pub fn percent_of_capacity(storage: u32, capacity: i32) -> Option<f64> {
+    if capacity <= 0 {
+        return None;
+    }
+    Some(storage as f64 / capacity as f64 * 100.0)
+}
+
+/// The `DailyFill` of every day of a reservoir's daily storage.
+pub fn daily_fill(values: &BTreeMap<NaiveDate, u32>, capacity: i32) -> Vec<DailyFill> {
+    let mut last_full: Option<NaiveDate> = None;
+    // (water year, highest storage of that water year so far)
+    let mut peak: Option<(WaterYear, u32)> = None;
+    values
+        .iter()
+        .map(|(date, storage)| {
+            if capacity > 0 && *storage >= capacity as u32 {
+                last_full = Some(*date);
+            }
+            let water_year = WaterYear::from_date(date);
+            let year_peak = match peak {
+                Some((year, value)) if year == water_year => value.max(*storage),
+                _ => *storage,
+            };
+            peak = Some((water_year, year_peak));
+            DailyFill {
+                date: *date,
+                storage: *storage,
+                percent_of_capacity: percent_of_capacity(*storage, capacity),
+                days_since_full: last_full.map(|full| (*date - full).num_days()),
+                drawdown: year_peak - storage,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{daily_fill, percent_of_capacity};
+    use chrono::NaiveDate;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_percent_of_capacity() {
+        assert_eq!(percent_of_capacity(50, 200), Some(25.0));
+        assert_eq!(percent_of_capacity(50, 0), None);
+    }
+
+    #[test]
+    fn test_daily_fill() {
+        let date =
+            |year: i32, month: u32, day: u32| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let values: BTreeMap<NaiveDate, u32> = vec![
+            (date(2021, 9, 29), 80),
+            (date(2021, 9, 30), 100),
+            (date(2021, 10, 1), 90),
+            (date(2021, 10, 3), 95),
+            (date(2021, 10, 4), 70),
+            (date(2022, 1, 1), 60),
+        ]
+        .into_iter()
+        .collect();
+        let fill = daily_fill(&values, 100);
+        assert_eq!(fill.len(), 6);
+        assert_eq!(fill[0].percent_of_capacity, Some(80.0));
+        assert_eq!(fill[0].days_since_full, None);
+        assert_eq!(fill[1].days_since_full, Some(0));
+        assert_eq!(fill[4].days_since_full, Some(4));
+        // the peak starts over with the water year, on October 1st
+        assert_eq!(fill[0].drawdown, 0);
+        assert_eq!(fill[2].drawdown, 0);
+        assert_eq!(fill[4].drawdown, 25);
+        // and not on January 1st
+        assert_eq!(fill[5].drawdown, 35);
+    }
+}
@@ -0,0 +1,247 @@
+//! Ways of filling in the values missing from a series, see
+//! `Observation::smooth_observations_with`.
+use crate::observation::Provenance;
+
+/// Fills the missing values of a series.
+///
+/// `known` holds the (position, value) of every value that is present, in
+/// order of position. A missing value at `x` lies after `known[i]` and,
+/// unless it trails the series (`i + 1 == known.len()`), before
+/// `known[i + 1]`. Returning `None` leaves it missing.
+pub trait GapFill {
+    fn value_at(&self, known: &[(f64, f64)], i: usize, x: f64) -> Option<f64>;
+
+    /// How the values filled in between two known values came about.
+    /// Trailing values are always `Provenance::CarriedForward`.
+    fn provenance(&self) -> Provenance {
+        Provenance::InterpolatedFromDaily
+    }
+}
+
+/// A straight line between the values either side of a gap; trailing
+/// gaps hold the last value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Linear;
+
+impl GapFill for Linear {
+    fn value_at(&self, known: &[(f64, f64)], i: usize, x: f64) -> Option<f64> {
+        let (x0, y0) = known[i];
+        match known.get(i + 1) {
+            Some((x1, y1)) => Some(y0 + (y1 - y0) * (x - x0) / (x1 - x0)),
+            None => Some(y0),
+        }
+    }
+}
+
+/// Holds the last value until the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Step;
+
+impl GapFill for Step {
+    fn value_at(&self, known: &[(f64, f64)], i: usize, _x: f64) -> Option<f64> {
+        Some(known[i].1)
+    }
+
+    fn provenance(&self) -> Provenance {
+        Provenance::CarriedForward
+    }
+}
+
+/// A monotone cubic (Fritsch-Butland) spline through the values around a
+/// gap, which curves like a spline but never overshoots the values either
+/// side of it; trailing gaps hold the last value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MonotoneCubic;
+
+impl MonotoneCubic {
+    // the slope of the spline at `known[k]`
+    fn tangent(known: &[(f64, f64)], k: usize) -> f64 {
+        let secant = |k: usize| {
+            let ((x0, y0), (x1, y1)) = (known[k], known[k + 1]);
+            (y1 - y0) / (x1 - x0)
+        };
+        if k == 0 {
+            return secant(0);
+        }
+        if k + 1 == known.len() {
+            return secant(k - 1);
+        }
+        let (before, after) = (secant(k - 1), secant(k));
+        if before * after <= 0.0 {
+            return 0.0;
+        }
+        let h_before = known[k].0 - known[k - 1].0;
+        let h_after = known[k + 1].0 - known[k].0;
+        3.0 * (h_before + h_after)
+            / ((2.0 * h_after + h_before) / before + (h_after + 2.0 * h_before) / after)
+    }
+}
+
+impl GapFill for MonotoneCubic {
+    fn value_at(&self, known: &[(f64, f64)], i: usize, x: f64) -> Option<f64> {
+        let (x0, y0) = known[i];
+        let (x1, y1) = match known.get(i + 1) {
+            Some(point) => *point,
+            None => return Some(y0),
+        };
+        let h = x1 - x0;
+        let t = (x - x0) / h;
+        let (t2, t3) = (t * t, t * t * t);
+        let m0 = MonotoneCubic::tangent(known, i);
+        let m1 = MonotoneCubic::tangent(known, i + 1);
+        Some(
+            (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                + (t3 - 2.0 * t2 + t) * h * m0
+                + (-2.0 * t3 + 3.0 * t2) * y1
+                + (t3 - t2) * h * m1,
+        )
+    }
+}
+
+/// Fills nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LeaveMissing;
+
+impl GapFill for LeaveMissing {
+    fn value_at(&self, _known: &[(f64, f64)], _i: usize, _x: f64) -> Option<f64> {
+        None
+    }
+}
+
+/// The strategy named `linear`, `step`, `monotone` or `none`.
+pub fn gap_fill_from_name(name: &str) -> Option<Box<dyn GapFill>> {
+    match name {
+        "linear" => Some(Box::new(Linear)),
+        "step" => Some(Box::new(Step)),
+        "monotone" => Some(Box::new(MonotoneCubic)),
+        "none" => Some(Box::new(LeaveMissing)),
+        _ => None,
+    }
+}
+
+/// Fills the `None`s of `values` with `strategy`, the position of a value
+/// being its index. Gaps longer than `max_gap` values stay missing, as do
+/// the values before the first one that is present.
+pub fn fill_gaps(values: &mut [Option<f64>], strategy: &dyn GapFill, max_gap: Option<usize>) {
+    let positions: Vec<f64> = (0..values.len()).map(|x| x as f64).collect();
+    fill_gaps_at(positions.as_slice(), values, strategy, max_gap);
+}
+
+/// `fill_gaps` with the position of every value given, e.g. its day, so
+/// that a gap is as long as the distance between the values either side
+/// of it however many values are missing in between. A trailing gap runs
+/// from the last value present to the last position.
+pub fn fill_gaps_at(
+    positions: &[f64],
+    values: &mut [Option<f64>],
+    strategy: &dyn GapFill,
+    max_gap: Option<usize>,
+) {
+    let present: Vec<usize> = values
+        .iter()
+        .enumerate()
+        .filter_map(|(i, value)| value.map(|_| i))
+        .collect();
+    let known: Vec<(f64, f64)> = present
+        .iter()
+        .map(|i| (positions[*i], values[*i].unwrap()))
+        .collect();
+    for (k, start) in present.iter().enumerate() {
+        let end = present.get(k + 1).cloned().unwrap_or(values.len());
+        if end - start == 1 {
+            continue;
+        }
+        let gap = match present.get(k + 1) {
+            Some(next) => positions[*next] - positions[*start] - 1.0,
+            None => positions[values.len() - 1] - positions[*start],
+        };
+        if matches!(max_gap, Some(max_gap) if gap > max_gap as f64) {
+            continue;
+        }
+        let missing = start + 1..end;
+        for (value, x) in values[missing.clone()].iter_mut().zip(&positions[missing]) {
+            *value = strategy.value_at(known.as_slice(), k, *x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fill_gaps, fill_gaps_at, gap_fill_from_name, LeaveMissing, Linear, MonotoneCubic, Step,
+    };
+
+    fn series() -> Vec<Option<f64>> {
+        vec![
+            None,
+            Some(0.0),
+            None,
+            None,
+            Some(30.0),
+            Some(40.0),
+            None,
+            Some(40.0),
+            None,
+        ]
+    }
+
+    #[test]
+    fn test_linear() {
+        let mut values = series();
+        fill_gaps(&mut values, &Linear, None);
+        assert_eq!(values[0], None);
+        assert_eq!(values[2], Some(10.0));
+        assert_eq!(values[3], Some(20.0));
+        assert_eq!(values[6], Some(40.0));
+        assert_eq!(values[8], Some(40.0));
+    }
+
+    #[test]
+    fn test_step_and_leave_missing() {
+        let mut values = series();
+        fill_gaps(&mut values, &Step, None);
+        assert_eq!(values[3], Some(0.0));
+        assert_eq!(values[8], Some(40.0));
+        let mut values = series();
+        fill_gaps(&mut values, &LeaveMissing, None);
+        assert_eq!(values, series());
+    }
+
+    #[test]
+    fn test_monotone_cubic() {
+        let mut values = series();
+        fill_gaps(&mut values, &MonotoneCubic, None);
+        let (two, three) = (values[2].unwrap(), values[3].unwrap());
+        assert!(0.0 < two && two < three && three < 30.0);
+        // flat between two equal values, with no overshoot
+        assert_eq!(values[6], Some(40.0));
+    }
+
+    #[test]
+    fn test_max_gap() {
+        let mut values = series();
+        fill_gaps(&mut values, &Linear, Some(1));
+        assert_eq!(values[2], None);
+        assert_eq!(values[3], None);
+        assert_eq!(values[6], Some(40.0));
+        assert_eq!(values[8], Some(40.0));
+        assert!(gap_fill_from_name("monotone").is_some());
+        assert!(gap_fill_from_name("quadratic").is_none());
+    }
+
+    #[test]
+    fn test_max_gap_in_positions() {
+        // days 0, 1, 2 and 5: one value missing, but four days between
+        // the values either side of it
+        let positions = [0.0, 1.0, 2.0, 5.0];
+        let mut values = vec![Some(0.0), None, Some(20.0), Some(50.0)];
+        fill_gaps_at(&positions, &mut values, &Linear, Some(1));
+        assert_eq!(values[1], Some(10.0));
+        let positions = [0.0, 4.0, 5.0];
+        let mut values = vec![Some(0.0), None, Some(50.0)];
+        fill_gaps_at(&positions, &mut values, &Linear, Some(3));
+        assert_eq!(values[1], None);
+        fill_gaps_at(&positions, &mut values, &Linear, Some(4));
+        assert_eq!(values[1], Some(40.0));
+    }
+}
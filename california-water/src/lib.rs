@@ -1,5 +1,25 @@
 #![feature(slice_group_by)]
 #![feature(array_chunks)]
+pub mod bundle;
+pub mod cache;
+#[cfg(feature = "chart")]
+pub mod chart;
+pub mod climatology;
+pub mod columnar;
 pub mod compression;
+pub mod error;
+#[cfg(feature = "arrow")]
+pub mod export;
+pub mod fill;
+pub mod gap_fill;
 pub mod observation;
+pub mod quality;
+pub mod query;
 pub mod reservoir;
+pub mod schedule;
+pub mod sensor;
+pub mod source;
+pub mod statewide;
+pub mod stream;
+pub mod value;
+pub mod water_year;
@@ -1,49 +1,287 @@
 use crate::{
-    compression::{decompress_tar_file_to_csv_string, TAR_OBJECT},
+    bundle::DatasetBundle,
+    error::{BundleError, RowError},
+    gap_fill::{fill_gaps, fill_gaps_at, GapFill, Linear},
     reservoir::Reservoir,
+    sensor::Sensor,
+    source::{CdecRequest, CdecSource},
+    statewide::ReservoirObservations,
 };
-use chrono::{format::format, naive::NaiveDate, Datelike};
-use core::{panic, result::Result};
+use chrono::{
+    format::format,
+    naive::{NaiveDate, NaiveDateTime},
+    Datelike,
+};
+use core::result::Result;
 use csv::{ReaderBuilder, StringRecord, ByteRecord};
 use futures::future::join_all;
-use reqwest::Client;
+use serde::Serialize;
 use std::{
     cmp::Ordering,
     collections::{BTreeMap, HashMap},
     str,
 };
 const DATE_FORMAT: &str = "%Y%m%d %H%M";
-const YEAR_FORMAT: &str = "%Y-%m-%d";
 const CSV_ROW_LENGTH: usize = 9;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum ObservationError {
-    HttpRequestError,
-    HttpResponseParseError,
-    ObservationCollectionError,
-    FunctionFail,
-}
+pub use crate::error::ObservationError;
+pub use crate::value::SensorValue;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Duration {
+    Hourly,
+    Event,
     Daily,
     Monthly,
 }
+
+impl Duration {
+    /// CDEC's `dur_code` for the duration.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Duration::Hourly => "H",
+            Duration::Event => "E",
+            Duration::Daily => "D",
+            Duration::Monthly => "M",
+        }
+    }
+}
+
+impl TryFrom<&str> for Duration {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "H" => Ok(Duration::Hourly),
+            "E" => Ok(Duration::Event),
+            "D" => Ok(Duration::Daily),
+            "M" => Ok(Duration::Monthly),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How sub-daily values are combined into one value per day.
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub enum DataRecording {
+pub enum DailyAggregate {
+    Mean,
+    Min,
+    Max,
+    Last,
+}
+
+/// How a long date range is split into separate CDEC requests.
+///
+/// CDEC is slow to answer, and sometimes gives up on, requests that span
+/// decades, so ranges are cut at every multiple of `years` calendar years
+/// (e.g. 1990-01-01 through 1999-12-31 for decades) and stitched back
+/// together afterwards.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DateChunks {
+    /// Calendar years per request, 0 to never split a range.
+    pub years: u32,
+    /// Whether the requests for one range may be in flight at the same time.
+    pub parallel: bool,
+}
+
+/// The daily observations of a station, filled in from its monthly ones,
+/// see `Observation::get_observations`.
+#[derive(Debug)]
+pub struct StationObservations<V = u32> {
+    pub observations: Vec<Observation<V>>,
+    /// Why the monthly observations could not be fetched, in which case
+    /// `observations` are the daily ones as they are.
+    pub monthly_error: Option<ObservationError>,
+}
+
+impl<V: SensorValue> StationObservations<V> {
+    /// `observations` filled in from the monthly ones, or left as they are
+    /// next to the error when the monthly request failed.
+    pub fn from_daily_and_monthly(
+        observations: Vec<Observation<V>>,
+        monthly_observations: Result<Vec<Observation<V>>, ObservationError>,
+    ) -> Self {
+        match monthly_observations {
+            Ok(monthly_observations) => StationObservations {
+                observations: Observation::fill_from_monthly_observations(
+                    observations,
+                    monthly_observations,
+                ),
+                monthly_error: None,
+            },
+            Err(e) => StationObservations {
+                observations,
+                monthly_error: Some(e),
+            },
+        }
+    }
+}
+
+impl DateChunks {
+    pub fn new(years: u32, parallel: bool) -> Self {
+        DateChunks { years, parallel }
+    }
+
+    /// The inclusive (start, end) windows covering `start_date` through
+    /// `end_date`.
+    pub fn split(
+        &self,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+    ) -> Vec<(NaiveDate, NaiveDate)> {
+        if self.years == 0 || start_date > end_date {
+            return vec![(*start_date, *end_date)];
+        }
+        let years = self.years as i32;
+        let mut windows = Vec::new();
+        let mut window_start = *start_date;
+        while window_start <= *end_date {
+            let next_year = (window_start.year().div_euclid(years) + 1) * years;
+            let next_start = match NaiveDate::from_ymd_opt(next_year, 1, 1) {
+                Some(next_start) => next_start,
+                None => {
+                    windows.push((window_start, *end_date));
+                    break;
+                }
+            };
+            let window_end = next_start.pred_opt().unwrap().min(*end_date);
+            windows.push((window_start, window_end));
+            window_start = next_start;
+        }
+        windows
+    }
+}
+
+impl Default for DateChunks {
+    fn default() -> Self {
+        DateChunks {
+            years: 10,
+            parallel: true,
+        }
+    }
+}
+
+/// A reading, serialized as a `status` with a `value` for recordings,
+/// e.g. `{"status":"recording","value":9593}` or `{"status":"art"}`.
+///
+/// Recordings are whole acre feet by default; sensors with fractional or
+/// negative values use another `SensorValue`, e.g. `DataRecording<f64>`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(tag = "status", content = "value", rename_all = "lowercase")]
+pub enum DataRecording<V = u32> {
+    /// Below the rating table, CDEC's `BRT`.
     Brt,
+    /// Above the rating table, CDEC's `ART`.
     Art,
+    /// No reading, CDEC's `---`.
+    #[serde(rename = "missing")]
     Dash,
-    Recording(u32),
+    /// A value that is not a number, e.g. blank or garbled, kept apart from
+    /// `Recording` so it is not mistaken for zero.
+    Invalid,
+    Recording(V),
 }
 
-#[derive(Debug, Clone)]
-pub struct Observation {
+/// CDEC's `DATA_FLAG`, a letter qualifying a reading.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DataFlag {
+    /// `e`, estimated rather than read off the sensor.
+    Estimated,
+    /// `r`, revised since it was first reported.
+    Revised,
+    /// `v`, outside the range the sensor is valid for.
+    OutOfRange,
+    /// Any other flag, as CDEC wrote it.
+    Other(char),
+}
+
+impl DataFlag {
+    pub fn from_code(code: char) -> Self {
+        match code {
+            'e' => DataFlag::Estimated,
+            'r' => DataFlag::Revised,
+            'v' => DataFlag::OutOfRange,
+            other => DataFlag::Other(other),
+        }
+    }
+
+    pub fn code(&self) -> char {
+        match self {
+            DataFlag::Estimated => 'e',
+            DataFlag::Revised => 'r',
+            DataFlag::OutOfRange => 'v',
+            DataFlag::Other(other) => *other,
+        }
+    }
+}
+
+/// Where a value came from: read off a sensor, or made up in its absence.
+///
+/// Written to the `DATA_FLAG` column of csv rows as its `code`, after
+/// CDEC's own flag. Measured values add nothing to the column, so that
+/// rows read as CDEC writes them.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provenance {
+    #[default]
+    Measured,
+    /// Filled in between daily values, see `smooth_observations_with`.
+    InterpolatedFromDaily,
+    /// Filled in between monthly values, see `fill_from_monthly_observations`.
+    InterpolatedFromMonthly,
+    /// The last value held past the end of a series, or up to the next one.
+    CarriedForward,
+    /// Put in place of a value that was missing or could not be trusted.
+    Imputed,
+}
+
+impl Provenance {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Provenance::Measured => "measured",
+            Provenance::InterpolatedFromDaily => "interpolated-daily",
+            Provenance::InterpolatedFromMonthly => "interpolated-monthly",
+            Provenance::CarriedForward => "carried-forward",
+            Provenance::Imputed => "imputed",
+        }
+    }
+
+    /// The provenance written as `code`, `None` for any other flag.
+    pub fn from_code(code: &str) -> Option<Provenance> {
+        match code.trim() {
+            "measured" => Some(Provenance::Measured),
+            "interpolated-daily" => Some(Provenance::InterpolatedFromDaily),
+            "interpolated-monthly" => Some(Provenance::InterpolatedFromMonthly),
+            "carried-forward" => Some(Provenance::CarriedForward),
+            "imputed" => Some(Provenance::Imputed),
+            _ => None,
+        }
+    }
+
+    pub fn is_measured(&self) -> bool {
+        *self == Provenance::Measured
+    }
+}
+
+/// One CDEC row. Serializes with ISO 8601 dates and the reading's status
+/// and value as fields of the observation itself.
+///
+/// `V` is the type of the value, see `SensorValue`: storage is read as
+/// `Observation`, an elevation as `Observation<f64>`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Observation<V = u32> {
     pub station_id: String,
-    pub date_observation: NaiveDate,
-    pub date_recording: NaiveDate,
-    pub value: DataRecording,
+    pub sensor: Sensor,
+    pub date_observation: NaiveDateTime,
+    pub date_recording: NaiveDateTime,
+    #[serde(flatten)]
+    pub value: DataRecording<V>,
     pub duration: Duration,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_flag: Option<DataFlag>,
+    pub provenance: Provenance,
 }
 
 impl Observation {
@@ -70,31 +308,76 @@ impl Observation {
     //     }))
     //     .await;
     // }
+    /// Every row of the historical snapshot: the embedded bundle, or the
+    /// bundle named by `CALIFORNIA_WATER_BUNDLE` when that one is newer.
+    /// Falls back to the embedded bundle when `CALIFORNIA_WATER_BUNDLE`
+    /// names one that can not be read, see `try_get_all_records` for why.
+    /// `stream::ObservationStream` reads a snapshot without holding all of
+    /// it in memory.
     pub fn get_all_records() -> Vec<StringRecord> {
-        let bytes_of_csv_string = decompress_tar_file_to_csv_string(TAR_OBJECT);
-        csv::ReaderBuilder::new()
-            .has_headers(false)
-            .from_reader(bytes_of_csv_string.as_slice())
-            .records()
-            .map(|x| x.expect("failed record parse"))
+        let bundle = DatasetBundle::current().unwrap_or_else(|_| DatasetBundle::embedded());
+        Observation::bundle_records(&bundle)
+    }
+
+    /// `get_all_records`, failing instead of falling back when
+    /// `CALIFORNIA_WATER_BUNDLE` names a bundle that can not be read.
+    pub fn try_get_all_records() -> Result<Vec<StringRecord>, BundleError> {
+        Ok(Observation::bundle_records(&DatasetBundle::current()?))
+    }
+
+    // the rows of every csv entry, one entry after the other
+    fn bundle_records(bundle: &DatasetBundle) -> Vec<StringRecord> {
+        bundle
+            .entries
+            .iter()
+            .flat_map(|entry| {
+                csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(entry.contents.as_slice())
+                    .into_records()
+                    .map(|x| x.expect("failed record parse"))
+            })
             .collect::<Vec<StringRecord>>()
     }
 
+    /// Sums the storage of every reservoir by day.
+    ///
+    /// Fails with the first station's error if any reservoir could not be
+    /// fetched, `get_all_reservoirs_observations_by_dates` keeps whatever
+    /// did succeed instead.
     pub async fn get_all_reservoirs_data_by_dates(
+        source: &dyn CdecSource,
         start_date: &NaiveDate,
         end_date: &NaiveDate,
     ) -> Result<BTreeMap<NaiveDate, u32>, ObservationError> {
+        let reservoir_observations =
+            Observation::get_all_reservoirs_observations_by_dates(source, start_date, end_date)
+                .await;
+        let total = reservoir_observations.total();
+        match reservoir_observations.failures.into_iter().next() {
+            Some(failure) => Err(failure.error),
+            None => Ok(total.values),
+        }
+    }
+
+    /// Fetches the storage of every reservoir, recording each station that
+    /// fails rather than giving up on the rest.
+    pub async fn get_all_reservoirs_observations_by_dates(
+        source: &dyn CdecSource,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+    ) -> ReservoirObservations {
         let reservoirs = Reservoir::get_reservoir_vector();
-        let mut date_water_btree: BTreeMap<NaiveDate, u32> = BTreeMap::new();
-        let client = Client::new();
+        let storage = Sensor::storage();
         let all_reservoir_observations = join_all(reservoirs.iter().map(|reservoir| {
-            let client_ref = &client;
+            let storage_ref = &storage;
             let start_date_ref = start_date;
             let end_date_ref = end_date;
             async move {
                 Observation::get_observations(
-                    client_ref,
+                    source,
                     reservoir.station_id.as_str(),
+                    storage_ref,
                     start_date_ref,
                     end_date_ref,
                 )
@@ -102,321 +385,431 @@ impl Observation {
             }
         }))
         .await;
-        for reservoir_observations in all_reservoir_observations {
-            let observations = reservoir_observations.unwrap();
-            for observation in observations {
-                let k = {
-                    if let DataRecording::Recording(v) = observation.value {
-                        v
-                    } else {
-                        0u32
-                    }
-                };
-                date_water_btree
-                    .entry(observation.date_observation)
-                    .and_modify(|e| *e += k)
-                    .or_insert(k);
+        ReservoirObservations::from_stations(
+            reservoirs
+                .into_iter()
+                .zip(all_reservoir_observations)
+                .collect(),
+        )
+    }
+
+    pub async fn get_string_records(
+        source: &dyn CdecSource,
+        reservoir_id: &str,
+        sensor: &Sensor,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+    ) -> Result<Vec<StringRecord>, ObservationError> {
+        let observations: Vec<Observation> =
+            Observation::get_observations(source, reservoir_id, sensor, start_date, end_date)
+                .await?
+                .observations;
+        let mut ans: Vec<StringRecord> = Vec::with_capacity(observations.len());
+        for obs in observations {
+            let row = format!("{} {}", obs.station_id, obs.date_observation);
+            match obs.try_into() {
+                Ok(record) => ans.push(record),
+                Err(source) => {
+                    return Err(ObservationError::Row {
+                        request: None,
+                        line: None,
+                        row,
+                        source,
+                    })
+                }
             }
         }
-        Ok(date_water_btree)
+        Ok(ans)
+    }
+    async fn http_request_body(
+        source: &dyn CdecSource,
+        request: &CdecRequest,
+    ) -> Result<String, ObservationError> {
+        source
+            .get_csv(request)
+            .await
+            .map_err(|source| ObservationError::Request {
+                request: Box::new(request.clone()),
+                source,
+            })
     }
+    fn request_to_string_records(
+        request_body: String,
+    ) -> Result<Vec<StringRecord>, ObservationError> {
+        ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(request_body.as_bytes())
+            .records()
+            .map(|x| {
+                x.map_err(|source| ObservationError::Csv {
+                    request: None,
+                    line: source.position().map(|position| position.line()),
+                    source,
+                })
+            })
+            .collect::<Result<Vec<StringRecord>, ObservationError>>()
+    }
+}
 
+// fetching, interpolating and aggregating work for any kind of value
+impl<V: SensorValue> Observation<V> {
+    /// Daily observations of a station, filled in from its monthly ones.
+    /// A station whose monthly request fails keeps its daily observations
+    /// as they are, with the error next to them, see `StationObservations`.
     pub async fn get_observations(
-        client: &Client,
+        source: &dyn CdecSource,
         reservoir_id: &str,
+        sensor: &Sensor,
         start_date: &NaiveDate,
         end_date: &NaiveDate,
-    ) -> Result<Vec<Observation>, ObservationError> {
-        let mut result: Result<Vec<Observation>, ObservationError> =
-            Err(ObservationError::FunctionFail);
-        let mut observations: Vec<Observation> = Vec::new();
-        let request_body_daily =
-            Observation::http_request_body(client, reservoir_id, start_date, end_date, "D").await;
-        let request_body_monthly =
-            Observation::http_request_body(client, reservoir_id, start_date, end_date, "M").await;
-        if let Ok(body) = request_body_daily {
-            if let Ok(mut daily_observations) = Observation::request_to_observations(body) {
-                observations.append(&mut daily_observations);
-            } else {
-                result = Err(ObservationError::HttpResponseParseError);
-            }
-        } else {
-            result = Err(ObservationError::HttpRequestError);
-        }
+    ) -> Result<StationObservations<V>, ObservationError> {
+        Observation::get_observations_in_chunks(
+            source,
+            reservoir_id,
+            sensor,
+            start_date,
+            end_date,
+            &DateChunks::default(),
+        )
+        .await
+    }
+
+    /// `get_observations` with control over how the date range is split
+    /// into requests.
+    pub async fn get_observations_in_chunks(
+        source: &dyn CdecSource,
+        reservoir_id: &str,
+        sensor: &Sensor,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+        chunks: &DateChunks,
+    ) -> Result<StationObservations<V>, ObservationError> {
+        let observations = Observation::get_observations_by_duration_in_chunks(
+            source,
+            reservoir_id,
+            sensor,
+            &Duration::Daily,
+            start_date,
+            end_date,
+            chunks,
+        )
+        .await?;
+        let monthly_observations = Observation::get_observations_by_duration_in_chunks(
+            source,
+            reservoir_id,
+            sensor,
+            &Duration::Monthly,
+            start_date,
+            end_date,
+            chunks,
+        )
+        .await;
+        Ok(StationObservations::from_daily_and_monthly(
+            observations,
+            monthly_observations,
+        ))
+    }
+
+    /// Adds daily observations interpolated from `monthly_observations` on
+    /// every day that `observations` has no recording for.
+    pub fn fill_from_monthly_observations(
+        observations: Vec<Observation<V>>,
+        monthly_observations: Vec<Observation<V>>,
+    ) -> Vec<Observation<V>> {
+        Observation::fill_from_monthly_observations_with(
+            observations,
+            monthly_observations,
+            &Linear,
+            None,
+        )
+    }
+
+    /// `fill_from_monthly_observations` with a choice of how the days
+    /// between monthly values are filled, see `gap_fill`. Gaps of more than
+    /// `max_gap` days between two monthly values are left as they are.
+    pub fn fill_from_monthly_observations_with(
+        mut observations: Vec<Observation<V>>,
+        mut monthly_observations: Vec<Observation<V>>,
+        strategy: &dyn GapFill,
+        max_gap: Option<usize>,
+    ) -> Vec<Observation<V>> {
         // collect monthly data and then
-        // 1. linearly interpolate to daily observations
+        // 1. interpolate to daily observations
         // 2. insert into observations if the date does not exist
-        if let Ok(body) = request_body_monthly {
-            if let Ok(mut monthly_observations) = Observation::request_to_observations(body) {
-                let mut observations_to_add_from_monthly_interpolations: Vec<Observation> =
-                    Vec::new();
-                // interpolate
-                let daily_observations_from_monthly_observations_interpolated: Vec<Observation> =
-                    Observation::linearly_interpolate_monthly_observations(
-                        &mut monthly_observations,
-                    );
-                for interpolated_observation in
-                    daily_observations_from_monthly_observations_interpolated
-                {
-                    let has_daily_value_is_recorded = observations.iter().any(|observation| {
-                        let has_observation = interpolated_observation.date_observation
-                            == observation.date_observation;
-                        let is_recording =
-                            matches!(observation.value, DataRecording::Recording(..));
-                        has_observation && is_recording
-                    });
-                    if !has_daily_value_is_recorded {
-                        observations_to_add_from_monthly_interpolations
-                            .push(interpolated_observation);
-                    }
-                }
-                observations.append(&mut observations_to_add_from_monthly_interpolations);
-            } else {
-                result = Err(ObservationError::HttpResponseParseError);
+        let mut observations_to_add_from_monthly_interpolations: Vec<Observation<V>> = Vec::new();
+        // interpolate
+        let daily_observations_from_monthly_observations_interpolated: Vec<Observation<V>> =
+            Observation::interpolate_monthly_observations_with(
+                &mut monthly_observations,
+                strategy,
+                max_gap,
+            );
+        for interpolated_observation in daily_observations_from_monthly_observations_interpolated {
+            let has_daily_value_is_recorded = observations.iter().any(|observation| {
+                let has_observation =
+                    interpolated_observation.date_observation == observation.date_observation;
+                let is_recording = matches!(observation.value, DataRecording::Recording(..));
+                has_observation && is_recording
+            });
+            if !has_daily_value_is_recorded {
+                observations_to_add_from_monthly_interpolations.push(interpolated_observation);
             }
+        }
+        observations.append(&mut observations_to_add_from_monthly_interpolations);
+        observations
+    }
+
+    /// Fetches one duration of a sensor exactly as CDEC reports it, without
+    /// filling in gaps from other durations. This is the way to get at
+    /// hourly (`H`) and event (`E`) data, which can then be reduced with
+    /// `aggregate_to_daily`.
+    pub async fn get_observations_by_duration(
+        source: &dyn CdecSource,
+        reservoir_id: &str,
+        sensor: &Sensor,
+        duration: &Duration,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+    ) -> Result<Vec<Observation<V>>, ObservationError> {
+        let request = CdecRequest::new(
+            reservoir_id,
+            sensor.number,
+            duration.code(),
+            start_date,
+            end_date,
+        );
+        let body = Observation::http_request_body(source, &request).await?;
+        Observation::request_to_observations(body).map_err(|e| e.with_request(&request))
+    }
+
+    /// `get_observations_by_duration` with one request per window of
+    /// `chunks`, stitched together in date order. Should windows overlap in
+    /// what CDEC sends back, the first observation of a timestamp is kept.
+    pub async fn get_observations_by_duration_in_chunks(
+        source: &dyn CdecSource,
+        reservoir_id: &str,
+        sensor: &Sensor,
+        duration: &Duration,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+        chunks: &DateChunks,
+    ) -> Result<Vec<Observation<V>>, ObservationError> {
+        let windows = chunks.split(start_date, end_date);
+        let all_window_observations = if chunks.parallel {
+            join_all(windows.iter().map(|(window_start, window_end)| {
+                Observation::get_observations_by_duration(
+                    source,
+                    reservoir_id,
+                    sensor,
+                    duration,
+                    window_start,
+                    window_end,
+                )
+            }))
+            .await
         } else {
-            result = Err(ObservationError::HttpRequestError);
+            let mut all_window_observations = Vec::with_capacity(windows.len());
+            for (window_start, window_end) in windows.iter() {
+                all_window_observations.push(
+                    Observation::get_observations_by_duration(
+                        source,
+                        reservoir_id,
+                        sensor,
+                        duration,
+                        window_start,
+                        window_end,
+                    )
+                    .await,
+                );
+            }
+            all_window_observations
+        };
+        let mut observations: Vec<Observation<V>> = Vec::new();
+        for window_observations in all_window_observations {
+            observations.append(&mut window_observations?);
         }
-        result = Ok(observations);
-        result
+        Observation::stitch_observations(&mut observations);
+        Ok(observations)
+    }
+
+    /// Sorts observations by date and drops repeated timestamps of the same
+    /// station, sensor and duration, keeping the first one.
+    pub fn stitch_observations(observations: &mut Vec<Observation<V>>) {
+        observations.sort_by_key(|observation| observation.date_observation);
+        observations.dedup_by(|a, b| {
+            a.date_observation == b.date_observation
+                && a.station_id == b.station_id
+                && a.sensor == b.sensor
+                && a.duration == b.duration
+        });
+    }
+
+    /// Reduces hourly or event observations to one daily observation per
+    /// station, sensor and day. Days without any recording keep the first
+    /// non-recording value (e.g. `---`) that was reported for them.
+    pub fn aggregate_to_daily(
+        observations: &[Observation<V>],
+        aggregate: DailyAggregate,
+    ) -> Vec<Observation<V>> {
+        let mut days: BTreeMap<(String, u32, NaiveDate), Vec<&Observation<V>>> = BTreeMap::new();
+        for observation in observations {
+            let key = (
+                observation.station_id.clone(),
+                observation.sensor.number,
+                observation.date_observation.date(),
+            );
+            days.entry(key).or_default().push(observation);
+        }
+        let mut output_vector: Vec<Observation<V>> = Vec::with_capacity(days.len());
+        for ((_, _, day), mut day_observations) in days {
+            day_observations.sort_by_key(|observation| observation.date_observation);
+            let values = day_observations
+                .iter()
+                .filter_map(|observation| match observation.value {
+                    DataRecording::Recording(v) => Some(v),
+                    _ => None,
+                })
+                .collect::<Vec<V>>();
+            let value = if values.is_empty() {
+                day_observations[0].value
+            } else {
+                let v = match aggregate {
+                    DailyAggregate::Mean => {
+                        let sum: f64 = values.iter().map(|v| v.to_f64()).sum();
+                        V::from_f64(sum / (values.len() as f64))
+                    }
+                    DailyAggregate::Min => values
+                        .iter()
+                        .copied()
+                        .reduce(|a, b| if b < a { b } else { a })
+                        .unwrap(),
+                    DailyAggregate::Max => values
+                        .iter()
+                        .copied()
+                        .reduce(|a, b| if b > a { b } else { a })
+                        .unwrap(),
+                    DailyAggregate::Last => values[values.len() - 1],
+                };
+                DataRecording::Recording(v)
+            };
+            let first = day_observations[0];
+            // a flag only holds for the day if it held for every reading
+            let data_flag = first.data_flag.filter(|flag| {
+                day_observations
+                    .iter()
+                    .all(|observation| observation.data_flag == Some(*flag))
+            });
+            let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+            output_vector.push(Observation {
+                station_id: first.station_id.clone(),
+                sensor: first.sensor.clone(),
+                date_observation: midnight,
+                date_recording: midnight,
+                value,
+                duration: Duration::Daily,
+                data_flag,
+                provenance: first.provenance,
+            });
+        }
+        output_vector
     }
 
-    fn linearly_interpolate_monthly_observations(
-        monthly_observations: &mut Vec<Observation>,
-    ) -> Vec<Observation> {
-        if monthly_observations.is_empty() {
+    // every monthly recording as a daily observation, and the days between
+    // two of them filled in by `strategy`; `---` and values that are not a
+    // number are gaps like any other
+    fn interpolate_monthly_observations_with(
+        monthly_observations: &mut [Observation<V>],
+        strategy: &dyn GapFill,
+        max_gap: Option<usize>,
+    ) -> Vec<Observation<V>> {
+        // a single monthly value has nothing to interpolate towards
+        if monthly_observations.len() < 2 {
             return Vec::new();
         }
-        monthly_observations.sort();
-        let mut output_vector: Vec<Observation> = Vec::new();
-        // 1. need to make sure we have a pair of values we can operate over.
-        let group_len = monthly_observations.len();
-        let mut markers: Vec<usize> = Vec::new();
-        let mut i: usize = 0;
-        // for the ith and (i+1)th element,
-        // 1. if ith element is a value and
-        //    (i+1)th is not, mark i
-        // 2. if not then ith element is
-        //    some error.  if (i+1)th
-        //    element is a value, then mark
-        //    (i+1)
-        loop {
-            let observation = &monthly_observations[i];
-            let next_observation = &monthly_observations[i + 1];
-            match (observation.value, next_observation.value) {
-                (DataRecording::Recording(..), DataRecording::Dash) => {
-                    markers.push(i);
-                    let monthly_recording_as_daily = Observation {
-                        station_id: observation.station_id.clone(),
-                        date_observation: observation.date_observation,
-                        date_recording: observation.date_recording,
-                        value: observation.value,
-                        duration: Duration::Daily,
-                    };
-                    output_vector.push(monthly_recording_as_daily);
-                }
-                (DataRecording::Recording(..), DataRecording::Art) => {
-                    markers.push(i);
-                    let monthly_recording_as_daily = Observation {
-                        station_id: observation.station_id.clone(),
-                        date_observation: observation.date_observation,
-                        date_recording: observation.date_recording,
-                        value: observation.value,
-                        duration: Duration::Daily,
-                    };
-                    output_vector.push(monthly_recording_as_daily);
-                }
-                (DataRecording::Recording(..), DataRecording::Brt) => {
-                    markers.push(i);
-                    let monthly_recording_as_daily = Observation {
-                        station_id: observation.station_id.clone(),
-                        date_observation: observation.date_observation,
-                        date_recording: observation.date_recording,
-                        value: observation.value,
-                        duration: Duration::Daily,
-                    };
-                    output_vector.push(monthly_recording_as_daily);
-                }
-                (DataRecording::Dash, DataRecording::Recording(..)) => {
-                    markers.push(i + 1);
-                    let monthly_recording_as_daily = Observation {
-                        station_id: next_observation.station_id.clone(),
-                        date_observation: next_observation.date_observation,
-                        date_recording: next_observation.date_recording,
-                        value: next_observation.value,
-                        duration: Duration::Daily,
-                    };
-                    output_vector.push(monthly_recording_as_daily);
-                }
-                (DataRecording::Art, DataRecording::Recording(..)) => {
-                    markers.push(i + 1);
-                    let monthly_recording_as_daily = Observation {
-                        station_id: next_observation.station_id.clone(),
-                        date_observation: next_observation.date_observation,
-                        date_recording: next_observation.date_recording,
-                        value: next_observation.value,
-                        duration: Duration::Daily,
-                    };
-                    output_vector.push(monthly_recording_as_daily);
-                }
-                (DataRecording::Brt, DataRecording::Recording(..)) => {
-                    markers.push(i + 1);
-                    let monthly_recording_as_daily = Observation {
-                        station_id: next_observation.station_id.clone(),
-                        date_observation: next_observation.date_observation,
-                        date_recording: next_observation.date_recording,
-                        value: next_observation.value,
-                        duration: Duration::Daily,
-                    };
-                    output_vector.push(monthly_recording_as_daily);
-                }
-                (DataRecording::Recording(..), DataRecording::Recording(..)) => {
-                    markers.push(i);
-                    markers.push(i + 1);
-                }
-                _ => {}
-            }
-            if i == (group_len - 2) {
-                break;
+        monthly_observations.sort_by_key(|observation| observation.date_observation);
+        let recordings: Vec<&Observation<V>> = monthly_observations
+            .iter()
+            .filter(|observation| matches!(observation.value, DataRecording::Recording(..)))
+            .collect();
+        let (first, last) = match (recordings.first(), recordings.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Vec::new(),
+        };
+        // one value per day from the first recording through the last
+        let day = |observation: &Observation<V>| {
+            (observation.date_observation - first.date_observation).num_days() as usize
+        };
+        let mut values: Vec<Option<f64>> = vec![None; day(last) + 1];
+        let mut recorded_on: Vec<Option<&Observation<V>>> = vec![None; day(last) + 1];
+        for recording in recordings.iter() {
+            if let DataRecording::Recording(value) = recording.value {
+                values[day(recording)] = Some(value.to_f64());
+                recorded_on[day(recording)] = Some(*recording);
             }
-            i += 1; // do not i+2; still need to loop one-by-one
         }
-        // for each array chunk pair[1]:
-        // 1. do linear interpolation
-        // 2. if markers is odd length, then
-        // 2.1 from markers[len-1] to last observation:
-        // 2.1.1 check there are no recordings, if so,
-        // 2.1.2 set all recordings from markers[len-1]+1 to last observation
-        //       to the value observation[markers[len-1]]
-        // [1] - https://play.rust-lang.org/?version=nightly&mode=debug&edition=2018&gist=75bb6330866854040404a619c09c04f7
-        let markers_slice = markers.as_slice();
-        for [x0usize, x1usize] in markers_slice.array_chunks::<2>() {
-            let x0 = *x0usize as u32;
-            let date_x0 = monthly_observations[*x0usize].date_observation;
-            let date_x1 = monthly_observations[*x1usize].date_observation;
-            let delta_x = (date_x1 - date_x0).num_days().abs() as usize;
-            let y0 = match monthly_observations[*x0usize].value {
-                DataRecording::Recording(k) => k,
-                _ => panic!("failed to select value"),
-            };
-            let y1 = match monthly_observations[*x1usize].value {
-                DataRecording::Recording(k) => k,
-                _ => panic!("failed to select value"),
-            };
-            let a = y1 - y0;
-            let m = (a as f64) / (delta_x as f64);
-            // just fill in entries between
-            for xi in 1..delta_x {
-                let y_i = (m * ((xi - (x0 as usize)) as f64) + (y0 as f64)).round() as u32;
-                //make a daily observation
-                let idx_duration = chrono::Duration::days(xi as i64);
-                let date_observation =
-                    monthly_observations[*x0usize].date_observation + idx_duration;
-                let date_recording = monthly_observations[*x0usize].date_recording + idx_duration;
-                let station_id = monthly_observations[*x0usize].station_id.clone();
-                let ith_day_observation = Observation {
+        fill_gaps(&mut values, strategy, max_gap);
+        // a strategy that interpolates here interpolates from monthly values
+        let provenance = match strategy.provenance() {
+            Provenance::InterpolatedFromDaily => Provenance::InterpolatedFromMonthly,
+            provenance => provenance,
+        };
+        let mut output_vector: Vec<Observation<V>> = Vec::new();
+        let mut previous = first;
+        for (xi, value) in values.into_iter().enumerate() {
+            if let Some(recording) = recorded_on[xi] {
+                previous = recording;
+                output_vector.push(Observation {
                     duration: Duration::Daily,
-                    value: DataRecording::Recording(y_i),
-                    date_observation,
-                    date_recording,
-                    station_id,
-                };
-                output_vector.push(ith_day_observation);
+                    provenance: Provenance::Measured,
+                    ..recording.clone()
+                });
+                continue;
             }
-            // add monthly_observations[*x0usize] and monthly_observations[*x1usize]
-            // as daily observations
-            let mut interpolated_thingers = vec![
-                Observation {
-                    duration: Duration::Daily,
-                    value: monthly_observations[*x0usize].value,
-                    date_observation: monthly_observations[*x0usize].date_observation,
-                    date_recording: monthly_observations[*x0usize].date_recording,
-                    station_id: monthly_observations[*x0usize].station_id.clone(),
-                },
-                Observation {
-                    duration: Duration::Daily,
-                    value: monthly_observations[*x1usize].value,
-                    date_observation: monthly_observations[*x1usize].date_observation,
-                    date_recording: monthly_observations[*x1usize].date_recording,
-                    station_id: monthly_observations[*x1usize].station_id.clone(),
-                },
-            ];
-            output_vector.append(&mut interpolated_thingers);
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            // make a daily observation
+            let idx_duration = chrono::Duration::days((xi - day(previous)) as i64);
+            output_vector.push(Observation {
+                station_id: previous.station_id.clone(),
+                sensor: previous.sensor.clone(),
+                date_observation: previous.date_observation + idx_duration,
+                date_recording: previous.date_recording + idx_duration,
+                value: DataRecording::Recording(V::from_f64(value)),
+                duration: Duration::Daily,
+                data_flag: None,
+                provenance,
+            });
         }
-        output_vector.sort();
-        output_vector.dedup();
         output_vector
     }
 
-    pub async fn get_string_records(
-        client: &Client,
-        reservoir_id: &str,
-        start_date: &NaiveDate,
-        end_date: &NaiveDate,
-    ) -> Result<Vec<StringRecord>, ObservationError> {
-        match Observation::get_observations(client, reservoir_id, start_date, end_date).await {
-            Ok(observations) => {
-                let mut ans: Vec<StringRecord> = Vec::with_capacity(observations.len());
-                for obs in observations {
-                    if let Ok(record) = obs.try_into() {
-                        ans.push(record);
-                    } else {
-                        panic!("failed to convert record");
-                    }
-                }
-                Ok(ans)
-            },
-            Err(e) => Err(e)
-        }
-
-    }
-    async fn http_request_body(
-        client: &Client,
-        reservoir_id: &str,
-        start_date: &NaiveDate,
-        end_date: &NaiveDate,
-        duration: &str,
-    ) -> Result<String, reqwest::Error> {
-        let url = format!("http://cdec.water.ca.gov/dynamicapp/req/CSVDataServlet?Stations={}&SensorNums=15&dur_code={}&Start={}&End={}", reservoir_id, duration, start_date.format(YEAR_FORMAT), end_date.format(YEAR_FORMAT));
-        let response = client.get(url).send().await?;
-        response.text().await
-    }
-    pub fn records_to_observations(vec_records: Vec<StringRecord>) -> Vec<Observation> {
+    pub fn records_to_observations(
+        vec_records: Vec<StringRecord>,
+    ) -> Result<Vec<Observation<V>>, ObservationError> {
         vec_records
-            .iter()
-            .map(|x| {
-                let y = x.clone();
-                y.try_into()
-            })
-            .collect::<Result<Vec<Observation>, _>>()
-            .unwrap()
+            .into_iter()
+            .map(Observation::record_to_observation)
+            .collect::<Result<Vec<Observation<V>>, _>>()
     }
-    fn request_to_observations(request_body: String) -> Result<Vec<Observation>, ObservationError> {
-        let string_records = Observation::request_to_string_records(request_body);
-        let result = string_records
-            .unwrap()
-            .iter()
-            .map(|x| {
-                let y = x.clone();
-                y.try_into()
+    fn record_to_observation(record: StringRecord) -> Result<Observation<V>, ObservationError> {
+        let line = record.position().map(|position| position.line());
+        record
+            .clone()
+            .try_into()
+            .map_err(|source| ObservationError::Row {
+                request: None,
+                line,
+                row: record.iter().collect::<Vec<&str>>().join(","),
+                source,
             })
-            .collect::<Result<Vec<Observation>, _>>();
-        if let Ok(records) = result {
-            Ok(records)
-        } else {
-            Err(ObservationError::ObservationCollectionError)
-        }
     }
-    fn request_to_string_records(
+    fn request_to_observations(
         request_body: String,
-    ) -> Result<Vec<StringRecord>, ObservationError> {
-        let records = ReaderBuilder::new()
-            .has_headers(true)
-            .from_reader(request_body.as_bytes())
-            .records()
-            .map(|x| x.expect("failed record parse"))
-            .collect::<Vec<StringRecord>>();
-        Ok(records)
+    ) -> Result<Vec<Observation<V>>, ObservationError> {
+        let string_records = Observation::request_to_string_records(request_body)?;
+        Observation::records_to_observations(string_records)
     }
     /// Suppose we have gaps in our observations, e.g.:
     ///
@@ -428,7 +821,8 @@ impl Observation {
     /// SHA,D,15,STORAGE,19850106 0000,19850106 0000,1694200,,AF
     ///
     /// `smooth_observations` does a linear interpolation of the
-    /// missing observations.
+    /// missing observations, and missing observations after the last
+    /// recording keep its value.
     ///
     /// From the example above, it becomes:
     /// SHA,D,15,STORAGE,19850101 0000,19850101 0000,1543200,,AF
@@ -437,120 +831,53 @@ impl Observation {
     /// SHA,D,15,STORAGE,19850104 0000,19850104 0000,1633800,,AF
     /// SHA,D,15,STORAGE,19850105 0000,19850105 0000,1664000,,AF
     /// SHA,D,15,STORAGE,19850106 0000,19850106 0000,1694200,,AF
-    pub fn smooth_observations(vec_records: &mut Vec<Observation>) -> Vec<Observation> {
-        let mut output_vector: Vec<Observation> = Vec::with_capacity(vec_records.len());
-        let observations_grouped_by_station_id = vec_records
-            .as_slice()
-            .group_by(|a, b| a.station_id == b.station_id);
-        // this for loop does two things:
-        // 1. Smoothy smoothy things by reservoir
-        // 2. places smoothed observations by reservoir into output_vector
+    pub fn smooth_observations(vec_records: &mut [Observation<V>]) -> Vec<Observation<V>> {
+        Observation::smooth_observations_with(vec_records, &Linear, None)
+    }
+
+    /// `smooth_observations` with a choice of how the gaps of each
+    /// reservoir are filled, see `gap_fill`. Gaps of more than `max_gap`
+    /// days are left as they are.
+    pub fn smooth_observations_with(
+        vec_records: &[Observation<V>],
+        strategy: &dyn GapFill,
+        max_gap: Option<usize>,
+    ) -> Vec<Observation<V>> {
+        let mut output_vector: Vec<Observation<V>> = Vec::with_capacity(vec_records.len());
+        let observations_grouped_by_station_id =
+            vec_records.group_by(|a, b| a.station_id == b.station_id);
         for group in observations_grouped_by_station_id {
             let mut sorted_group = Vec::from(group);
             // sorting is the key step into the next flow
-            sorted_group.sort();
-            let sorted_group_len = sorted_group.len();
-            let mut markers: Vec<usize> = Vec::new();
-            let mut i: usize = 0;
-            // for the ith and (i+1)th element,
-            // 1. if ith element is a value and
-            //    (i+1)th is not, mark i
-            // 2. if not then ith element is
-            //    some error.  if (i+1)th
-            //    element is a value, then mark
-            //    (i+1)
-            loop {
-                let observation = &sorted_group[i];
-                let next_observation = &sorted_group[i + 1];
-                match (observation.value, next_observation.value) {
-                    (DataRecording::Recording(..), DataRecording::Dash) => {
-                        markers.push(i);
-                    }
-                    (DataRecording::Recording(..), DataRecording::Art) => {
-                        markers.push(i);
-                    }
-                    (DataRecording::Recording(..), DataRecording::Brt) => {
-                        markers.push(i);
-                    }
-                    (DataRecording::Dash, DataRecording::Recording(..)) => {
-                        markers.push(i + 1);
-                    }
-                    (DataRecording::Art, DataRecording::Recording(..)) => {
-                        markers.push(i + 1);
-                    }
-                    (DataRecording::Brt, DataRecording::Recording(..)) => {
-                        markers.push(i + 1);
-                    }
-                    _ => {}
-                }
-                if i == (sorted_group_len - 2) {
-                    println!("catching the break");
-                    break;
+            sorted_group.sort_by_key(|observation| observation.date_observation);
+            let mut values: Vec<Option<f64>> = sorted_group
+                .iter()
+                .map(|observation| match observation.value {
+                    DataRecording::Recording(value) => Some(value.to_f64()),
+                    _ => None,
+                })
+                .collect();
+            let last_known = values.iter().rposition(|value| value.is_some());
+            // in days, so rows CDEC left out count towards a gap too
+            let first_date = sorted_group[0].date_observation;
+            let positions: Vec<f64> = sorted_group
+                .iter()
+                .map(|observation| {
+                    (observation.date_observation - first_date).num_minutes() as f64 / 1440.0
+                })
+                .collect();
+            fill_gaps_at(positions.as_slice(), &mut values, strategy, max_gap);
+            for (i, (observation, value)) in sorted_group.iter_mut().zip(values).enumerate() {
+                if matches!(observation.value, DataRecording::Recording(..)) {
+                    continue;
                 }
-                i += 1; // do not i+2; still need to loop one-by-one
-            }
-            // for each array chunk pair[1]:
-            // 1. do linear interpolation
-            // 2. if markers is odd length, then
-            // 2.1 from markers[len-1] to last observation:
-            // 2.1.1 check there are no recordings, if so,
-            // 2.1.2 set all recordings from markers[len-1]+1 to last observation
-            //       to the value observation[markers[len-1]]
-            // [1] - https://play.rust-lang.org/?version=nightly&mode=debug&edition=2018&gist=75bb6330866854040404a619c09c04f7
-            let markers_slice = markers.as_slice();
-            for [x0usize, x1usize] in markers_slice.array_chunks::<2>() {
-                let x0 = *x0usize as u32;
-                let x1 = *x1usize as u32;
-                let y0 = match sorted_group[*x0usize].value {
-                    DataRecording::Recording(k) => k,
-                    _ => panic!("failed to select value"),
-                };
-                let y1 = match sorted_group[*x1usize].value {
-                    DataRecording::Recording(k) => k,
-                    _ => panic!("failed to select value"),
-                };
-                let a = y1 - y0;
-                let b = x1 - x0;
-                let m = (a as f64) / (b as f64);
-
-                for x_i in x0..x1 {
-                    if x_i == x0 {
-                        continue;
-                    }
-                    let y_i = (m * ((x_i - x0) as f64) + (y0 as f64)).round() as u32;
-                    let x_i_as_usize = x_i as usize;
-                    sorted_group[x_i_as_usize].value = DataRecording::Recording(y_i);
-                }
-            } // step 1
-              // step 2
-            let markers_len = markers.len();
-            if markers_len % 2 == 1 {
-                let mut xi = markers[markers_len - 1];
-                // step 2.1.1
-                let mut is_need_of_filling = true;
-                loop {
-                    if let DataRecording::Recording(..) = sorted_group[xi].value {
-                        is_need_of_filling = false;
-                        break;
-                    }
-                    if xi == sorted_group_len {
-                        break;
-                    }
-                    xi += 1;
-                }
-                // step 2.1.2
-                if is_need_of_filling {
-                    let k = sorted_group[markers[markers_len - 1]].value;
-                    for item in sorted_group
-                        .iter_mut()
-                        .take(sorted_group_len)
-                        .skip(markers[markers_len - 1] + 1)
-                    {
-                        item.value = k;
-                    }
-                    // for idx in (markers[markers_len-1] + 1)..group_len {
-                    //     sorted_group[idx].value = k;
-                    // }
+                if let Some(value) = value {
+                    observation.value = DataRecording::Recording(V::from_f64(value));
+                    observation.data_flag = None;
+                    observation.provenance = match last_known {
+                        Some(last_known) if i < last_known => strategy.provenance(),
+                        _ => Provenance::CarriedForward,
+                    };
                 }
             }
             output_vector.append(&mut sorted_group);
@@ -559,9 +886,9 @@ impl Observation {
     }
 
     pub fn vector_to_hashmap(
-        vec_observations: Vec<Observation>,
-    ) -> HashMap<String, Vec<Observation>> {
-        let mut result: HashMap<String, Vec<Observation>> = HashMap::new();
+        vec_observations: Vec<Observation<V>>,
+    ) -> HashMap<String, Vec<Observation<V>>> {
+        let mut result: HashMap<String, Vec<Observation<V>>> = HashMap::new();
         let groups = vec_observations
             .as_slice()
             .group_by(|a, b| a.station_id == b.station_id);
@@ -573,115 +900,137 @@ impl Observation {
     }
 }
 
-impl TryFrom<Observation> for StringRecord {
-    fn try_from(value: Observation) -> Result<Self, Self::Error> {
+impl<V: SensorValue> TryFrom<Observation<V>> for StringRecord {
+    fn try_from(value: Observation<V>) -> Result<Self, Self::Error> {
         //         r#"STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
         // VIL,D,15,STORAGE,20220215 0000,20220215 0000,9593, ,AF";
         let station_id = value.station_id.to_uppercase();
         let station_id_str = station_id.as_str();
-        let duration = match value.duration {
-            Duration::Daily => "D",
-            Duration::Monthly => "M",
-        };
-        let sensor_number = "15";
-        let sensor_type = "STORAGE";
-        let date_time = format!(
-            "{}{:02}{:02} 0000",
-            value.date_recording.year(),
-            value.date_recording.month(),
-            value.date_recording.day()
-        );
+        let duration = value.duration.code();
+        let sensor_number = value.sensor.number.to_string();
+        let sensor_number_str = sensor_number.as_str();
+        let sensor_type = value.sensor.kind.as_str();
+        let date_time = value.date_recording.format(DATE_FORMAT).to_string();
         let date_time_str = date_time.as_str();
-        let date_obs = format!(
-            "{}{:02}{:02} 0000",
-            value.date_observation.year(),
-            value.date_observation.month(),
-            value.date_observation.day()
-        );
+        let date_obs = value.date_observation.format(DATE_FORMAT).to_string();
         let date_obs_str = date_obs.as_str();
         let val = match value.value {
             DataRecording::Recording(a) => a.to_string(),
             DataRecording::Art => String::from("ART"),
             DataRecording::Brt => String::from("BRT"),
             DataRecording::Dash => String::from("---"),
+            DataRecording::Invalid => String::new(),
         };
         let val_str = val.as_str();
-        let data_flag = "";
-        let units = "AF";
+        let data_flag = format_data_flag(value.data_flag, value.provenance);
+        let data_flag_str = data_flag.as_str();
+        let units = value.sensor.units.as_str();
         let b = ByteRecord::from(vec![
             station_id_str,
             duration,
-            sensor_number,
+            sensor_number_str,
             sensor_type,
             date_time_str,
             date_obs_str,
             val_str,
-            data_flag,
+            data_flag_str,
             units,
         ]);
-        match StringRecord::from_byte_record(b) {
-            Ok(s) => Ok(s),
-            Err(_) => Err(()),
-        }
+        StringRecord::from_byte_record(b).map_err(|e| RowError::Utf8(e.utf8_error().clone()))
     }
 
-    type Error = ();
+    type Error = RowError;
 }
 
-impl TryFrom<StringRecord> for Observation {
-    type Error = ();
+impl<V: SensorValue> TryFrom<StringRecord> for Observation<V> {
+    type Error = RowError;
 
     fn try_from(value: StringRecord) -> Result<Self, Self::Error> {
         if value.len() != CSV_ROW_LENGTH {
-            return Err(());
+            return Err(RowError::Length(value.len()));
         }
-        let duration = match value.get(1).unwrap() {
-            "D" => Ok(Duration::Daily),
-            "M" => Ok(Duration::Monthly),
-            _ => Err(()),
-        };
-        let date_recording_value = NaiveDate::parse_from_str(value.get(4).unwrap(), DATE_FORMAT);
-        let date_observation_value = NaiveDate::parse_from_str(value.get(5).unwrap(), DATE_FORMAT);
-        let data_value: Result<DataRecording, ()> = match value.get(6).unwrap() {
-            "BRT" => Ok(DataRecording::Brt),
-            "ART" => Ok(DataRecording::Art),
-            "---" => Ok(DataRecording::Dash),
-            s => match s.parse::<u32>() {
-                Err(_p) => Ok(DataRecording::Recording(0u32)),
-                Ok(u) => Ok(DataRecording::Recording(u)),
+        let duration = Duration::try_from(&value[1])
+            .map_err(|_| RowError::Duration(String::from(&value[1])))?;
+        let sensor_number = value[2]
+            .trim()
+            .parse::<u32>()
+            .map_err(RowError::SensorNumber)?;
+        let sensor = Sensor::new(sensor_number, value[3].trim(), value[8].trim());
+        let date_recording =
+            NaiveDateTime::parse_from_str(&value[4], DATE_FORMAT).map_err(RowError::Date)?;
+        let date_observation =
+            NaiveDateTime::parse_from_str(&value[5], DATE_FORMAT).map_err(RowError::Date)?;
+        let data_value = match &value[6] {
+            "BRT" => DataRecording::Brt,
+            "ART" => DataRecording::Art,
+            "---" => DataRecording::Dash,
+            s => match V::parse(s.trim()) {
+                None => DataRecording::Invalid,
+                Some(u) => DataRecording::Recording(u),
             },
-            // _ => Err(()),
         };
-        if let Ok(..) = duration {
-            return Ok(Observation {
-                station_id: value.get(0).unwrap().to_string(),
-                date_recording: date_recording_value.unwrap(),
-                date_observation: date_observation_value.unwrap(),
-                value: data_value.unwrap(),
-                duration: duration.unwrap(),
-            });
+        let (data_flag, provenance) = parse_data_flag(&value[7]);
+        Ok(Observation {
+            station_id: value[0].to_string(),
+            sensor,
+            date_recording,
+            date_observation,
+            value: data_value,
+            duration,
+            data_flag,
+            provenance,
+        })
+    }
+}
+
+// The `DATA_FLAG` column: CDEC's flag followed by the provenance of values
+// that were not measured, e.g. `e`, `interpolated-daily` or `e imputed`.
+fn format_data_flag(data_flag: Option<DataFlag>, provenance: Provenance) -> String {
+    let mut column = data_flag
+        .map(|flag| flag.code().to_string())
+        .unwrap_or_default();
+    if !provenance.is_measured() {
+        if !column.is_empty() {
+            column.push(' ');
         }
-        Err(())
+        column.push_str(provenance.code());
     }
+    column
 }
 
-impl Ord for Observation {
+// Reads `format_data_flag` back, and CDEC's own column, whose flags are
+// single letters and which is a space when there is none.
+fn parse_data_flag(column: &str) -> (Option<DataFlag>, Provenance) {
+    let mut data_flag = None;
+    let mut provenance = Provenance::Measured;
+    for word in column.split_whitespace() {
+        match Provenance::from_code(word) {
+            Some(code) => provenance = code,
+            None => data_flag = word.chars().next().map(DataFlag::from_code),
+        }
+    }
+    (data_flag, provenance)
+}
+
+// ordered by date; values that are not `Eq`, like `f64`, have no order
+impl<V: Eq> Ord for Observation<V> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.date_observation.cmp(&other.date_observation)
     }
 }
-impl Eq for Observation {}
+impl<V: Eq> Eq for Observation<V> {}
 
-impl PartialEq for Observation {
+impl<V: PartialEq> PartialEq for Observation<V> {
     fn eq(&self, other: &Self) -> bool {
         self.date_observation == other.date_observation
             && self.station_id == other.station_id
+            && self.sensor == other.sensor
             && self.date_recording == other.date_recording
             && self.value == other.value
     }
 }
 
-impl PartialOrd for Observation {
+impl<V: Eq> PartialOrd for Observation<V> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         if self.station_id != other.station_id {
             return None;
@@ -690,13 +1039,45 @@ impl PartialOrd for Observation {
     }
 }
 
+#[cfg(test)]
+impl<V> Observation<V> {
+    /// A measured storage observation at midnight of `date`, shared by
+    /// the tests of every module.
+    pub(crate) fn fixture(
+        station_id: &str,
+        duration: Duration,
+        date: NaiveDate,
+        value: DataRecording<V>,
+    ) -> Self {
+        let date = date.and_hms_opt(0, 0, 0).unwrap();
+        Observation {
+            station_id: String::from(station_id),
+            sensor: Sensor::storage(),
+            date_observation: date,
+            date_recording: date,
+            value,
+            duration,
+            data_flag: None,
+            provenance: Provenance::Measured,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{DataRecording, Duration};
+    use super::{
+        DailyAggregate, DataFlag, DataRecording, DateChunks, Duration, ObservationError, Provenance,
+    };
+    use crate::bundle::{BUNDLE_PATH_LOCK, BUNDLE_PATH_VARIABLE};
+    use crate::error::RowError;
+    use crate::gap_fill::{Linear, Step};
     use crate::observation::Observation;
-    use chrono::NaiveDate;
-    use csv::StringRecord;
-    use reqwest::Client;
+    use crate::reservoir::Reservoir;
+    use crate::sensor::Sensor;
+    use crate::source::CdecRequest;
+    use crate::source::FixtureSource;
+    use chrono::{Datelike, NaiveDate};
+    use csv::{ReaderBuilder, StringRecord};
     use std::assert_ne;
 
     // https://cdec.water.ca.gov/dynamicapp/req/CSVDataServlet?Stations=VIL&SensorNums=15&dur_code=D&Start=2022-02-15&End=2022-02-28
@@ -717,18 +1098,71 @@ VIL,D,15,STORAGE,20220227 0000,20220227 0000,9597, ,AF
 VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
 "#;
 
+    const STR_MONTHLY_RESULT: &str = r#"STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+"#;
+
+    fn vil_fixture_source() -> FixtureSource {
+        let mut source = FixtureSource::new();
+        source
+            .insert("VIL", "D", STR_RESULT)
+            .insert("VIL", "M", STR_MONTHLY_RESULT);
+        source
+    }
+
+    // every reservoir answers, but only VIL has any data
+    fn statewide_fixture_source() -> FixtureSource {
+        let mut source = FixtureSource::new();
+        for reservoir in Reservoir::get_reservoir_vector() {
+            source
+                .insert(reservoir.station_id.as_str(), "D", STR_MONTHLY_RESULT)
+                .insert(reservoir.station_id.as_str(), "M", STR_MONTHLY_RESULT);
+        }
+        source.insert("VIL", "D", STR_RESULT);
+        source
+    }
+
     #[cfg(not(target_family = "wasm"))]
     #[tokio::test]
     async fn test_get_all_reservoirs_data_by_dates() {
+        let source = statewide_fixture_source();
         let start_date = NaiveDate::from_ymd(2022, 02, 15);
         let end_date = NaiveDate::from_ymd(2022, 02, 28);
-        let obs = Observation::get_all_reservoirs_data_by_dates(&start_date, &end_date)
+        let obs = Observation::get_all_reservoirs_data_by_dates(&source, &start_date, &end_date)
             .await
             .unwrap();
+        assert_eq!(obs.len(), 14);
         for (_, val) in obs.iter() {
             assert_ne!(*val, 0u32);
         }
     }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_get_all_reservoirs_observations_partial_failure() {
+        let mut source = statewide_fixture_source();
+        source
+            .fixtures
+            .remove(&(String::from("SHA"), String::from("D")));
+        let start_date = NaiveDate::from_ymd_opt(2022, 2, 15).unwrap();
+        let end_date = NaiveDate::from_ymd_opt(2022, 2, 28).unwrap();
+        let reservoir_observations =
+            Observation::get_all_reservoirs_observations_by_dates(&source, &start_date, &end_date)
+                .await;
+        assert_eq!(reservoir_observations.failed_station_ids(), vec!["SHA"]);
+        assert_eq!(
+            reservoir_observations.series.len(),
+            Reservoir::get_reservoir_vector().len() - 1
+        );
+        let total = reservoir_observations.total();
+        assert!(!total.is_complete);
+        assert!(total.missing_capacity_fraction > 0.0 && total.missing_capacity_fraction < 1.0);
+        assert_eq!(total.values.len(), 14);
+        let error = Observation::get_all_reservoirs_data_by_dates(&source, &start_date, &end_date)
+            .await
+            .unwrap_err();
+        assert_eq!(error.station_id(), Some("SHA"));
+    }
+
     #[cfg(not(target_family = "wasm"))]
     #[tokio::test]
     async fn test_http_request_body() {
@@ -738,10 +1172,9 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         let reservoir_id = "VIL";
         let start_date = NaiveDate::from_ymd(2022, 02, 15);
         let end_date = NaiveDate::from_ymd(2022, 02, 28);
-        let client = Client::new();
-        let observations =
-            Observation::http_request_body(&client, reservoir_id, &start_date, &end_date, "D")
-                .await;
+        let source = vil_fixture_source();
+        let request = CdecRequest::new(reservoir_id, 15, "D", &start_date, &end_date);
+        let observations = Observation::http_request_body(&source, &request).await;
         assert_eq!(
             observations.unwrap().as_str().replace("\r\n", "\n"),
             STR_RESULT
@@ -757,10 +1190,174 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         let reservoir_id = "VIL";
         let start_date = NaiveDate::from_ymd(2022, 02, 15);
         let end_date = NaiveDate::from_ymd(2022, 02, 28);
-        let client = Client::new();
-        let observations =
-            Observation::get_observations(&client, reservoir_id, &start_date, &end_date).await;
-        assert_eq!(observations.unwrap().len(), 14);
+        let source = vil_fixture_source();
+        let observations = Observation::<u32>::get_observations(
+            &source,
+            reservoir_id,
+            &Sensor::storage(),
+            &start_date,
+            &end_date,
+        )
+        .await;
+        assert_eq!(observations.unwrap().observations.len(), 14);
+    }
+
+    #[test]
+    fn test_date_chunks_split() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let decades = DateChunks::default().split(&date(1985, 3, 1), &date(2022, 2, 28));
+        assert_eq!(
+            decades,
+            vec![
+                (date(1985, 3, 1), date(1989, 12, 31)),
+                (date(1990, 1, 1), date(1999, 12, 31)),
+                (date(2000, 1, 1), date(2009, 12, 31)),
+                (date(2010, 1, 1), date(2019, 12, 31)),
+                (date(2020, 1, 1), date(2022, 2, 28)),
+            ]
+        );
+        let years = DateChunks::new(1, false).split(&date(2021, 6, 1), &date(2022, 3, 1));
+        assert_eq!(
+            years,
+            vec![
+                (date(2021, 6, 1), date(2021, 12, 31)),
+                (date(2022, 1, 1), date(2022, 3, 1)),
+            ]
+        );
+        let whole = DateChunks::new(0, false).split(&date(1985, 3, 1), &date(2022, 2, 28));
+        assert_eq!(whole, vec![(date(1985, 3, 1), date(2022, 2, 28))]);
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_get_observations_in_chunks() {
+        let start_date = NaiveDate::from_ymd_opt(2021, 6, 1).unwrap();
+        let end_date = NaiveDate::from_ymd_opt(2022, 3, 1).unwrap();
+        for parallel in [true, false] {
+            let mut source = vil_fixture_source();
+            // the same rows again, as a window overlapping the first would give
+            source.insert(
+                "VIL",
+                "D",
+                &format!(
+                    "{}{}",
+                    STR_RESULT,
+                    &STR_RESULT[STR_RESULT.find('\n').unwrap() + 1..]
+                ),
+            );
+            let observations = Observation::<u32>::get_observations_in_chunks(
+                &source,
+                "VIL",
+                &Sensor::storage(),
+                &start_date,
+                &end_date,
+                &DateChunks::new(1, parallel),
+            )
+            .await
+            .unwrap()
+            .observations;
+            assert_eq!(observations.len(), 14);
+            assert!(observations
+                .windows(2)
+                .all(|pair| pair[0].date_observation < pair[1].date_observation));
+            let requests = source.requests();
+            assert_eq!(requests.len(), 4);
+            assert!(requests.iter().any(|request| request.dur_code == "D"
+                && request.start_date == NaiveDate::from_ymd_opt(2022, 1, 1).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_get_all_records_fallback() {
+        let _lock = BUNDLE_PATH_LOCK.lock().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("bundle.tar.xz");
+        std::fs::write(&path, b"not a bundle").unwrap();
+        std::env::set_var(BUNDLE_PATH_VARIABLE, &path);
+        let error = Observation::try_get_all_records();
+        let records = Observation::get_all_records();
+        std::env::remove_var(BUNDLE_PATH_VARIABLE);
+        // the embedded rows, and why the bundle on disk was not used
+        assert!(error.is_err());
+        assert_eq!(&records[0][0], "SHA");
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_get_observations_monthly_error() {
+        // the monthly request fails, the daily observations are kept next
+        // to its error
+        let mut source = FixtureSource::new();
+        source.insert("VIL", "D", STR_RESULT);
+        let station = Observation::<u32>::get_observations(
+            &source,
+            "VIL",
+            &Sensor::storage(),
+            &NaiveDate::from_ymd_opt(2022, 2, 15).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 28).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(station.observations.len(), 14);
+        let error = station.monthly_error.unwrap();
+        assert_eq!(error.station_id(), Some("VIL"));
+        assert_eq!(error.request().unwrap().dur_code, "M");
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_get_observations_request_error() {
+        let source = vil_fixture_source();
+        let start_date = NaiveDate::from_ymd_opt(2022, 2, 15).unwrap();
+        let end_date = NaiveDate::from_ymd_opt(2022, 2, 28).unwrap();
+        let error = Observation::<u32>::get_observations(
+            &source,
+            "SHA",
+            &Sensor::storage(),
+            &start_date,
+            &end_date,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, ObservationError::Request { .. }));
+        assert_eq!(error.station_id(), Some("SHA"));
+        assert_eq!(error.request().unwrap().start_date, start_date);
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_get_observations_row_error() {
+        let mut source = vil_fixture_source();
+        source.insert(
+            "VIL",
+            "D",
+            "STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+VIL,D,15,STORAGE,20220215 0000,20220215 0000,9593, ,AF
+VIL,D,15,STORAGE,2022-02-16,20220216 0000,9589, ,AF
+",
+        );
+        let error = Observation::<u32>::get_observations(
+            &source,
+            "VIL",
+            &Sensor::storage(),
+            &NaiveDate::from_ymd_opt(2022, 2, 15).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 28).unwrap(),
+        )
+        .await
+        .unwrap_err();
+        match &error {
+            ObservationError::Row {
+                request,
+                line,
+                row,
+                source: RowError::Date(..),
+            } => {
+                assert_eq!(request.as_ref().unwrap().dur_code, "D");
+                assert_eq!(*line, Some(3));
+                assert!(row.contains("2022-02-16"));
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
     }
 
     #[test]
@@ -773,6 +1370,55 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         assert_eq!(observations[0].value, DataRecording::Recording(9593));
     }
 
+    // https://cdec.water.ca.gov/dynamicapp/req/CSVDataServlet?Stations=ORO&SensorNums=15&dur_code=H&Start=2022-02-15&End=2022-02-16
+    const STR_HOURLY_RESULT: &str = r#"STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+ORO,H,15,STORAGE,20220215 2200,20220215 2200,1835870, ,AF
+ORO,H,15,STORAGE,20220215 2300,20220215 2300,1835950, ,AF
+ORO,H,15,STORAGE,20220216 0000,20220216 0000,1836030, ,AF
+ORO,H,15,STORAGE,20220216 0100,20220216 0100,---, ,AF
+ORO,H,15,STORAGE,20220216 0200,20220216 0200,1836190, ,AF
+"#;
+
+    #[test]
+    fn test_request_to_observations_hourly() {
+        let observations =
+            Observation::<u32>::request_to_observations(String::from(STR_HOURLY_RESULT)).unwrap();
+        assert_eq!(observations.len(), 5);
+        assert_eq!(observations[0].duration, Duration::Hourly);
+        assert_eq!(
+            observations[1].date_observation,
+            NaiveDate::from_ymd_opt(2022, 2, 15)
+                .unwrap()
+                .and_hms_opt(23, 0, 0)
+                .unwrap()
+        );
+        let record: StringRecord = observations[1].clone().try_into().unwrap();
+        assert_eq!(&record[1], "H");
+        assert_eq!(&record[5], "20220215 2300");
+    }
+
+    #[test]
+    fn test_aggregate_to_daily() {
+        let observations =
+            Observation::request_to_observations(String::from(STR_HOURLY_RESULT)).unwrap();
+        let mean = Observation::aggregate_to_daily(&observations, DailyAggregate::Mean);
+        assert_eq!(mean.len(), 2);
+        assert_eq!(mean[0].duration, Duration::Daily);
+        assert_eq!(
+            mean[0].date_observation,
+            NaiveDate::from_ymd_opt(2022, 2, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+        assert_eq!(mean[0].value, DataRecording::Recording(1835910));
+        assert_eq!(mean[1].value, DataRecording::Recording(1836110));
+        let last = Observation::aggregate_to_daily(&observations, DailyAggregate::Last);
+        assert_eq!(last[1].value, DataRecording::Recording(1836190));
+        let min = Observation::aggregate_to_daily(&observations, DailyAggregate::Min);
+        assert_eq!(min[1].value, DataRecording::Recording(1836030));
+    }
+
     #[test]
     fn test_smooth_observations() {
         /// SHA,D,15,STORAGE,19850101 0000,19850101 0000,1543200,,AF
@@ -784,89 +1430,125 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         let expected_observations = vec![
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 01),
-                date_recording: NaiveDate::from_ymd(1985, 01, 01),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1543200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 02),
-                date_recording: NaiveDate::from_ymd(1985, 01, 02),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1573400),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 03),
-                date_recording: NaiveDate::from_ymd(1985, 01, 03),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1603600),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 04),
-                date_recording: NaiveDate::from_ymd(1985, 01, 04),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1633800),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 05),
-                date_recording: NaiveDate::from_ymd(1985, 01, 05),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1664000),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 06),
-                date_recording: NaiveDate::from_ymd(1985, 01, 06),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1694200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
         ];
         let mut test_sample = vec![
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 01),
-                date_recording: NaiveDate::from_ymd(1985, 01, 01),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1543200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 02),
-                date_recording: NaiveDate::from_ymd(1985, 01, 02),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
                 value: DataRecording::Dash,
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 03),
-                date_recording: NaiveDate::from_ymd(1985, 01, 03),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
                 value: DataRecording::Dash,
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 04),
-                date_recording: NaiveDate::from_ymd(1985, 01, 04),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
                 value: DataRecording::Art,
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 05),
-                date_recording: NaiveDate::from_ymd(1985, 01, 05),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
                 value: DataRecording::Brt,
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 06),
-                date_recording: NaiveDate::from_ymd(1985, 01, 06),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1694200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
         ];
         let smooth_operator = Observation::smooth_observations(&mut test_sample);
@@ -876,6 +1558,43 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         )
     }
 
+    #[test]
+    fn test_smooth_observations_with() {
+        let observation = |day: u32, value: DataRecording| {
+            let date = NaiveDate::from_ymd_opt(1985, 1, day).unwrap();
+            Observation::fixture("SHA", Duration::Daily, date, value)
+        };
+        let test_sample = vec![
+            observation(1, DataRecording::Recording(300)),
+            observation(2, DataRecording::Dash),
+            observation(3, DataRecording::Recording(100)),
+            observation(4, DataRecording::Art),
+            observation(5, DataRecording::Art),
+        ];
+        let step = Observation::smooth_observations_with(&test_sample, &Step, None);
+        let values: Vec<DataRecording> = step.iter().map(|observation| observation.value).collect();
+        assert_eq!(
+            values,
+            vec![
+                DataRecording::Recording(300),
+                DataRecording::Recording(300),
+                DataRecording::Recording(100),
+                DataRecording::Recording(100),
+                DataRecording::Recording(100),
+            ]
+        );
+        // storage going down no longer underflows
+        let linear = Observation::smooth_observations_with(&test_sample, &Linear, Some(1));
+        assert_eq!(linear[1].value, DataRecording::Recording(200));
+        assert_eq!(linear[4].value, DataRecording::Art);
+        // filled values say how they were filled
+        assert_eq!(step[0].provenance, Provenance::Measured);
+        assert_eq!(step[1].provenance, Provenance::CarriedForward);
+        assert_eq!(step[4].provenance, Provenance::CarriedForward);
+        assert_eq!(linear[1].provenance, Provenance::InterpolatedFromDaily);
+        assert_eq!(linear[4].provenance, Provenance::Measured);
+    }
+
     #[test]
     fn test_linearly_interpolate_observations() {
         /// SHA,D,15,STORAGE,19850101 0000,19850101 0000,1543200,,AF
@@ -887,69 +1606,100 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         let expected_observations = vec![
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 01),
-                date_recording: NaiveDate::from_ymd(1985, 01, 01),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1543200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 02),
-                date_recording: NaiveDate::from_ymd(1985, 01, 02),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1573400),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 03),
-                date_recording: NaiveDate::from_ymd(1985, 01, 03),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1603600),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 04),
-                date_recording: NaiveDate::from_ymd(1985, 01, 04),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1633800),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 05),
-                date_recording: NaiveDate::from_ymd(1985, 01, 05),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1664000),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 06),
-                date_recording: NaiveDate::from_ymd(1985, 01, 06),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1694200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
         ];
         let mut test_sample = vec![
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 01),
-                date_recording: NaiveDate::from_ymd(1985, 01, 01),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1543200),
                 duration: Duration::Monthly,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 06),
-                date_recording: NaiveDate::from_ymd(1985, 01, 06),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1694200),
                 duration: Duration::Monthly,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
         ];
         let smooth_operator =
-            Observation::linearly_interpolate_monthly_observations(&mut test_sample);
+            Observation::interpolate_monthly_observations_with(&mut test_sample, &Linear, None);
         assert_eq!(
             smooth_operator, expected_observations,
             "failed to smooth observations"
-        )
+        );
+        let provenances: Vec<Provenance> = smooth_operator
+            .iter()
+            .map(|observation| observation.provenance)
+            .collect();
+        assert_eq!(provenances[0], Provenance::Measured);
+        assert_eq!(provenances[1], Provenance::InterpolatedFromMonthly);
+        assert_eq!(provenances[provenances.len() - 1], Provenance::Measured);
     }
 
     #[test]
@@ -963,93 +1713,197 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         let expected_observations = vec![
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 01),
-                date_recording: NaiveDate::from_ymd(1985, 01, 01),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1543200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 02),
-                date_recording: NaiveDate::from_ymd(1985, 01, 02),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 02).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1573400),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 03),
-                date_recording: NaiveDate::from_ymd(1985, 01, 03),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1603600),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 04),
-                date_recording: NaiveDate::from_ymd(1985, 01, 04),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 04).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1633800),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 05),
-                date_recording: NaiveDate::from_ymd(1985, 01, 05),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 05).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1664000),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 06),
-                date_recording: NaiveDate::from_ymd(1985, 01, 06),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1694200),
                 duration: Duration::Daily,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
         ];
         let mut test_sample = vec![
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 01),
-                date_recording: NaiveDate::from_ymd(1985, 01, 01),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 01).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1543200),
                 duration: Duration::Monthly,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 03),
-                date_recording: NaiveDate::from_ymd(1985, 01, 03),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 03).and_hms(0, 0, 0),
                 value: DataRecording::Dash,
                 duration: Duration::Monthly,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
             Observation {
                 station_id: String::from("SHA"),
-                date_observation: NaiveDate::from_ymd(1985, 01, 06),
-                date_recording: NaiveDate::from_ymd(1985, 01, 06),
+                sensor: Sensor::storage(),
+                date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+                date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
                 value: DataRecording::Recording(1694200),
                 duration: Duration::Monthly,
+                data_flag: None,
+                provenance: Provenance::Measured,
             },
         ];
         let smooth_operator =
-            Observation::linearly_interpolate_monthly_observations(&mut test_sample);
+            Observation::interpolate_monthly_observations_with(&mut test_sample, &Linear, None);
         assert_eq!(
             smooth_operator, expected_observations,
             "failed to smooth observations"
         )
     }
+
+    #[test]
+    fn test_linearly_interpolate_around_invalid_observations() {
+        let monthly = |day: u32, value: DataRecording| {
+            let date = NaiveDate::from_ymd_opt(1985, 1, day).unwrap();
+            Observation::fixture("SHA", Duration::Monthly, date, value)
+        };
+        // a value that is not a number is a gap like any other
+        for gap in [
+            DataRecording::Invalid,
+            DataRecording::Dash,
+            DataRecording::Art,
+        ] {
+            let mut test_sample = vec![
+                monthly(1, DataRecording::Recording(100)),
+                monthly(3, gap),
+                monthly(6, DataRecording::Recording(200)),
+            ];
+            let values: Vec<DataRecording> =
+                Observation::interpolate_monthly_observations_with(&mut test_sample, &Linear, None)
+                    .iter()
+                    .map(|observation| observation.value)
+                    .collect();
+            assert_eq!(
+                values,
+                [100, 120, 140, 160, 180, 200]
+                    .iter()
+                    .map(|value| DataRecording::Recording(*value))
+                    .collect::<Vec<DataRecording>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_interpolate_monthly_observations_with_strategy() {
+        let monthly = |day: u32, value: u32| {
+            let date = NaiveDate::from_ymd_opt(1985, 1, day).unwrap();
+            Observation::fixture(
+                "SHA",
+                Duration::Monthly,
+                date,
+                DataRecording::Recording(value),
+            )
+        };
+        let mut test_sample = vec![monthly(1, 100), monthly(4, 200), monthly(10, 300)];
+        let observations =
+            Observation::interpolate_monthly_observations_with(&mut test_sample, &Step, Some(2));
+        let values: Vec<(u32, DataRecording, Provenance)> = observations
+            .iter()
+            .map(|observation| {
+                (
+                    observation.date_observation.day(),
+                    observation.value,
+                    observation.provenance,
+                )
+            })
+            .collect();
+        // two days between the first values are held, the five days
+        // between the last two are too many
+        assert_eq!(
+            values,
+            vec![
+                (1, DataRecording::Recording(100), Provenance::Measured),
+                (2, DataRecording::Recording(100), Provenance::CarriedForward),
+                (3, DataRecording::Recording(100), Provenance::CarriedForward),
+                (4, DataRecording::Recording(200), Provenance::Measured),
+                (10, DataRecording::Recording(300), Provenance::Measured),
+            ]
+        );
+    }
+
     #[test]
     fn test_observation_to_stringrecord() {
         /// SHA,D,15,STORAGE,19850106 0000,19850106 0000,1694200,,AF
         let obs_daily = Observation {
             station_id: String::from("SHA"),
-            date_observation: NaiveDate::from_ymd(1985, 01, 06),
-            date_recording: NaiveDate::from_ymd(1985, 01, 06),
+            sensor: Sensor::storage(),
+            date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+            date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
             value: DataRecording::Recording(1694200),
             duration: Duration::Daily,
+            data_flag: None,
+            provenance: Provenance::Measured,
         };
         let obs_monthly = Observation {
             station_id: String::from("SHA"),
-            date_observation: NaiveDate::from_ymd(1985, 01, 06),
-            date_recording: NaiveDate::from_ymd(1985, 01, 06),
+            sensor: Sensor::storage(),
+            date_observation: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
+            date_recording: NaiveDate::from_ymd(1985, 01, 06).and_hms(0, 0, 0),
             value: DataRecording::Recording(1694200),
             duration: Duration::Monthly,
+            data_flag: None,
+            provenance: Provenance::Measured,
         };
         let obs_daily_string_record: StringRecord = obs_daily.try_into().unwrap();
         let obs_monthly_string_record: StringRecord = obs_monthly.try_into().unwrap();
@@ -1065,4 +1919,212 @@ VIL,D,15,STORAGE,20220228 0000,20220228 0000,9597, ,AF
         assert_eq!(&obs_monthly_string_record[1], "M");
 
     }
+
+    #[test]
+    fn test_observation_to_json() {
+        let date = NaiveDate::from_ymd_opt(2022, 2, 15)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let mut obs = Observation {
+            station_id: String::from("VIL"),
+            sensor: Sensor::storage(),
+            date_observation: date,
+            date_recording: date,
+            value: DataRecording::Recording(9593),
+            duration: Duration::Daily,
+            data_flag: None,
+            provenance: Provenance::Measured,
+        };
+        assert_eq!(
+            serde_json::to_value(&obs).unwrap(),
+            serde_json::json!({
+                "station_id": "VIL",
+                "sensor": {"number": 15, "kind": "STORAGE", "units": "AF"},
+                "date_observation": "2022-02-15T00:00:00",
+                "date_recording": "2022-02-15T00:00:00",
+                "status": "recording",
+                "value": 9593,
+                "duration": "daily",
+                "provenance": "measured"
+            })
+        );
+        obs.value = DataRecording::Dash;
+        let json = serde_json::to_value(&obs).unwrap();
+        assert_eq!(json["status"], "missing");
+        assert!(json.get("value").is_none());
+        let reservoir = serde_json::to_value(&Reservoir::get_reservoir_vector()[0]).unwrap();
+        assert!(reservoir["station_id"].is_string());
+        assert!(reservoir["capacity"].is_number());
+    }
+
+    #[test]
+    fn test_sensor_round_trip() {
+        // SHA,D,6,RES ELE,20220215 0000,20220215 0000,1000, ,FEET
+        let obs = Observation {
+            station_id: String::from("SHA"),
+            sensor: Sensor::reservoir_elevation(),
+            date_observation: NaiveDate::from_ymd_opt(2022, 2, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            date_recording: NaiveDate::from_ymd_opt(2022, 2, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            value: DataRecording::Recording(1000),
+            duration: Duration::Daily,
+            data_flag: None,
+            provenance: Provenance::Measured,
+        };
+        let record: StringRecord = obs.clone().try_into().unwrap();
+        assert_eq!(&record[2], "6");
+        assert_eq!(&record[3], "RES ELE");
+        assert_eq!(&record[8], "FEET");
+        let round_trip: Observation = record.try_into().unwrap();
+        assert_eq!(round_trip.sensor, Sensor::reservoir_elevation());
+        assert_eq!(round_trip, obs);
+    }
+
+    #[test]
+    fn test_provenance_round_trip() {
+        let date = NaiveDate::from_ymd_opt(2022, 2, 15)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        for provenance in [
+            Provenance::Measured,
+            Provenance::InterpolatedFromDaily,
+            Provenance::InterpolatedFromMonthly,
+            Provenance::CarriedForward,
+            Provenance::Imputed,
+        ] {
+            let obs = Observation {
+                station_id: String::from("VIL"),
+                sensor: Sensor::storage(),
+                date_observation: date,
+                date_recording: date,
+                value: DataRecording::Recording(9593),
+                duration: Duration::Daily,
+                data_flag: None,
+                provenance,
+            };
+            let record: StringRecord = obs.try_into().unwrap();
+            if !provenance.is_measured() {
+                assert_eq!(&record[7], provenance.code());
+            }
+            let round_trip: Observation = record.try_into().unwrap();
+            assert_eq!(round_trip.provenance, provenance);
+        }
+        // CDEC writes a blank flag as a space
+        let cdec = StringRecord::from(vec![
+            "VIL",
+            "D",
+            "15",
+            "STORAGE",
+            "20220215 0000",
+            "20220215 0000",
+            "9593",
+            " ",
+            "AF",
+        ]);
+        let observation: Observation = cdec.try_into().unwrap();
+        assert_eq!(observation.provenance, Provenance::Measured);
+    }
+
+    #[test]
+    fn test_data_flags_and_invalid_values() {
+        let csv = "VIL,D,15,STORAGE,20220215 0000,20220215 0000,9593,e,AF
+VIL,D,15,STORAGE,20220216 0000,20220216 0000,9589,r imputed,AF
+VIL,D,15,STORAGE,20220217 0000,20220217 0000,n/a,v,AF
+VIL,D,15,STORAGE,20220218 0000,20220218 0000,,N,AF
+";
+        let observations = Observation::<u32>::records_to_observations(
+            ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(csv.as_bytes())
+                .records()
+                .map(|record| record.unwrap())
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(observations[0].data_flag, Some(DataFlag::Estimated));
+        assert_eq!(observations[0].provenance, Provenance::Measured);
+        assert_eq!(observations[1].data_flag, Some(DataFlag::Revised));
+        assert_eq!(observations[1].provenance, Provenance::Imputed);
+        // not a number is not zero
+        assert_eq!(observations[2].value, DataRecording::Invalid);
+        assert_eq!(observations[2].data_flag, Some(DataFlag::OutOfRange));
+        assert_eq!(observations[3].value, DataRecording::Invalid);
+        assert_eq!(observations[3].data_flag, Some(DataFlag::Other('N')));
+        assert_eq!(observations[0].sensor.units, "AF");
+        let record: StringRecord = observations[1].clone().try_into().unwrap();
+        assert_eq!(&record[7], "r imputed");
+        let record: StringRecord = observations[2].clone().try_into().unwrap();
+        assert_eq!(&record[6], "");
+        assert_eq!(&record[7], "v");
+        let json = serde_json::to_value(&observations[2]).unwrap();
+        assert_eq!(json["status"], "invalid");
+        assert_eq!(json["data_flag"], "out-of-range");
+    }
+
+    #[test]
+    fn test_linearly_interpolate_decreasing_observations() {
+        // storage that goes down between two monthly values used to underflow
+        let monthly = |day: u32, value: u32| {
+            let date = NaiveDate::from_ymd_opt(1985, 1, day).unwrap();
+            Observation::fixture(
+                "SHA",
+                Duration::Monthly,
+                date,
+                DataRecording::Recording(value),
+            )
+        };
+        let mut test_sample = vec![monthly(1, 100), monthly(3, 80), monthly(5, 100)];
+        let values: Vec<DataRecording> =
+            Observation::interpolate_monthly_observations_with(&mut test_sample, &Linear, None)
+                .iter()
+                .map(|observation| observation.value)
+                .collect();
+        assert_eq!(
+            values,
+            [100, 90, 80, 90, 100]
+                .iter()
+                .map(|value| DataRecording::Recording(*value))
+                .collect::<Vec<DataRecording>>()
+        );
+    }
+
+    #[test]
+    fn test_fractional_and_negative_values() {
+        let csv = "PNF,H,6,RES ELE,20220215 0000,20220215 0000,1043.52, ,FEET
+PNF,H,6,RES ELE,20220215 0100,20220215 0100,1043.48, ,FEET
+PNF,H,6,RES ELE,20220215 0200,20220215 0200,---, ,FEET
+PNF,H,6,RES ELE,20220215 0300,20220215 0300,-3.5, ,FEET
+";
+        let records: Vec<StringRecord> = ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv.as_bytes())
+            .records()
+            .map(|record| record.unwrap())
+            .collect();
+        // whole numbers have no room for them
+        let storage = Observation::<u32>::records_to_observations(records.clone()).unwrap();
+        assert_eq!(storage[0].value, DataRecording::Invalid);
+        assert_eq!(storage[3].value, DataRecording::Invalid);
+        let elevation = Observation::<f64>::records_to_observations(records).unwrap();
+        assert_eq!(elevation[0].value, DataRecording::Recording(1043.52));
+        assert_eq!(elevation[3].value, DataRecording::Recording(-3.5));
+        let record: StringRecord = elevation[3].clone().try_into().unwrap();
+        assert_eq!(&record[6], "-3.5");
+        let json = serde_json::to_value(&elevation[0]).unwrap();
+        assert_eq!(json["value"], 1043.52);
+        let close_to = |value: DataRecording<f64>, expected: f64| matches!(value, DataRecording::Recording(value) if (value - expected).abs() < 1e-9);
+        let smoothed = Observation::smooth_observations(&mut elevation.clone());
+        assert!(close_to(smoothed[2].value, 519.99));
+        let daily = Observation::aggregate_to_daily(&elevation[..2], DailyAggregate::Mean);
+        assert!(close_to(daily[0].value, 1043.5));
+        let min = Observation::aggregate_to_daily(&elevation, DailyAggregate::Min);
+        assert_eq!(min[0].value, DataRecording::Recording(-3.5));
+    }
 }
@@ -0,0 +1,381 @@
+//! Checks for storage no reservoir could have, e.g. a day ten times the
+//! day before, and their replacement with values filled in around them.
+use crate::{
+    gap_fill::{fill_gaps_at, GapFill},
+    observation::{DataRecording, Observation, Provenance},
+};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt};
+
+/// How far a value may stray before it is implausible.
+///
+/// Changes are fractions of the reservoir's capacity, or of the previous
+/// value for reservoirs without a capacity on record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityChecks {
+    /// How far above capacity storage may go, e.g. `0.1` for 110%.
+    pub capacity_margin: f64,
+    /// The largest change from one day to the next.
+    pub max_daily_change: f64,
+    /// The largest drop below both neighbors that they do not share.
+    pub max_dip: f64,
+    /// Repeats of one value, in a row, that make a sensor stuck.
+    pub stuck_days: usize,
+}
+
+impl Default for QualityChecks {
+    fn default() -> Self {
+        QualityChecks {
+            capacity_margin: 0.1,
+            max_daily_change: 0.1,
+            max_dip: 0.02,
+            stuck_days: 30,
+        }
+    }
+}
+
+/// Why a value is implausible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Issue {
+    AboveCapacity,
+    /// A change from the last plausible value beyond `max_daily_change`
+    /// a day that the next value does not keep to.
+    Jump,
+    /// A drop beyond `max_dip` that both neighbors climb back out of.
+    Dip,
+    /// The same value `stuck_days` times in a row, the first of them
+    /// being left alone. Empty reservoirs may well stay at zero, and
+    /// storage that shifted to a new level may well stay there.
+    Stuck,
+}
+
+impl Issue {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Issue::AboveCapacity => "above capacity",
+            Issue::Jump => "jump",
+            Issue::Dip => "dip",
+            Issue::Stuck => "stuck",
+        }
+    }
+}
+
+/// A value that fails a check, `index` being its position in the
+/// observations that were checked.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImplausibleValue {
+    pub index: usize,
+    pub date: NaiveDate,
+    pub value: u32,
+    pub issue: Issue,
+}
+
+/// A value that was replaced, or left as it was when there was nothing
+/// to fill it in from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Replacement {
+    pub station_id: String,
+    pub date: NaiveDate,
+    pub issue: Issue,
+    pub original: u32,
+    pub replacement: Option<u32>,
+}
+
+impl fmt::Display for Replacement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}: {}",
+            self.station_id,
+            self.date,
+            self.issue.name(),
+            self.original
+        )?;
+        match self.replacement {
+            Some(replacement) => write!(f, " replaced with {}", replacement),
+            None => write!(f, " left as it was"),
+        }
+    }
+}
+
+/// What replacing the implausible values of many reservoirs changed.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct QualityReport {
+    pub replacements: Vec<Replacement>,
+}
+
+impl QualityReport {
+    /// The number of values that were replaced.
+    pub fn replaced(&self) -> usize {
+        self.replacements
+            .iter()
+            .filter(|replacement| replacement.replacement.is_some())
+            .count()
+    }
+}
+
+// how much storage may change by, scaled by `fraction`
+fn allowance(fraction: f64, capacity: i32, previous: u32) -> f64 {
+    if capacity > 0 {
+        fraction * capacity as f64
+    } else {
+        fraction * previous as f64
+    }
+}
+
+/// The implausible values of one reservoir's observations, in date order.
+/// The first recording is taken to be plausible.
+pub fn find_implausible(
+    observations: &[Observation],
+    capacity: i32,
+    checks: &QualityChecks,
+) -> Vec<ImplausibleValue> {
+    // (index, date, value) of every recording, by date
+    let mut points: Vec<(usize, NaiveDate, u32)> = observations
+        .iter()
+        .enumerate()
+        .filter_map(|(index, observation)| match observation.value {
+            DataRecording::Recording(value) => {
+                Some((index, observation.date_observation.date(), value))
+            }
+            _ => None,
+        })
+        .collect();
+    points.sort_by_key(|(_, date, _)| *date);
+    let mut issues: Vec<Option<Issue>> = vec![None; points.len()];
+    // above capacity
+    if capacity > 0 {
+        let ceiling = capacity as f64 * (1.0 + checks.capacity_margin);
+        for (issue, (_, _, value)) in issues.iter_mut().zip(points.iter()) {
+            if *value as f64 > ceiling {
+                *issue = Some(Issue::AboveCapacity);
+            }
+        }
+    }
+    // jumps from the last plausible value, unless the next plausible value
+    // stays with them and storage really did shift
+    let exceeds = |from: (NaiveDate, u32), to: (NaiveDate, u32)| {
+        let days = (to.0 - from.0).num_days().max(1) as f64;
+        let change = (to.1 as f64 - from.1 as f64).abs();
+        change > days * allowance(checks.max_daily_change, capacity, from.1)
+    };
+    let mut shifts: Vec<bool> = vec![false; points.len()];
+    let mut last: Option<(NaiveDate, u32)> = None;
+    for i in 0..points.len() {
+        if issues[i].is_some() {
+            continue;
+        }
+        let (_, date, value) = points[i];
+        if matches!(last, Some(last) if exceeds(last, (date, value))) {
+            let next = (i + 1..points.len()).find(|next| issues[*next].is_none());
+            let shifted = match next {
+                Some(next) => !exceeds((date, value), (points[next].1, points[next].2)),
+                None => false,
+            };
+            if !shifted {
+                issues[i] = Some(Issue::Jump);
+                continue;
+            }
+            shifts[i] = true;
+        }
+        last = Some((date, value));
+    }
+    // stuck, unless the run is storage settling at a level it shifted to
+    let mut run_start = 0;
+    for i in 1..=points.len() {
+        if i < points.len() && points[i].2 == points[run_start].2 {
+            continue;
+        }
+        let repeats = i - run_start;
+        let stuck = checks.stuck_days > 0 && repeats >= checks.stuck_days;
+        if stuck && points[run_start].2 > 0 && !shifts[run_start] {
+            for issue in issues.iter_mut().take(i).skip(run_start + 1) {
+                issue.get_or_insert(Issue::Stuck);
+            }
+        }
+        run_start = i;
+    }
+    // dips below both plausible neighbors
+    let plausible: Vec<usize> = (0..points.len()).filter(|i| issues[*i].is_none()).collect();
+    for neighbors in plausible.windows(3) {
+        let (before, at, after) = (
+            points[neighbors[0]].2,
+            points[neighbors[1]].2,
+            points[neighbors[2]].2,
+        );
+        let dip = allowance(checks.max_dip, capacity, before);
+        let drop_before = before as f64 - at as f64;
+        let drop_after = after as f64 - at as f64;
+        let neighbors_agree = (after as f64 - before as f64).abs() <= dip;
+        if drop_before > dip && drop_after > dip && neighbors_agree {
+            issues[neighbors[1]] = Some(Issue::Dip);
+        }
+    }
+    points
+        .into_iter()
+        .zip(issues)
+        .filter_map(|((index, date, value), issue)| {
+            issue.map(|issue| ImplausibleValue {
+                index,
+                date,
+                value,
+                issue,
+            })
+        })
+        .collect()
+}
+
+/// Replaces the implausible values of one reservoir's observations with
+/// values filled in by `strategy` from the plausible ones, marked
+/// `Provenance::Imputed`. Other gaps are left alone, as are implausible
+/// values with nothing to fill them in from. Sorts the observations by
+/// date.
+pub fn replace_implausible(
+    observations: &mut [Observation],
+    capacity: i32,
+    checks: &QualityChecks,
+    strategy: &dyn GapFill,
+    max_gap: Option<usize>,
+) -> Vec<Replacement> {
+    observations.sort();
+    let implausible = find_implausible(observations, capacity, checks);
+    let flagged: BTreeSet<usize> = implausible.iter().map(|value| value.index).collect();
+    let mut values: Vec<Option<f64>> = observations
+        .iter()
+        .enumerate()
+        .map(|(index, observation)| match observation.value {
+            DataRecording::Recording(value) if !flagged.contains(&index) => Some(value as f64),
+            _ => None,
+        })
+        .collect();
+    // in days, like `Observation::smooth_observations_with`
+    let positions: Vec<f64> = observations
+        .iter()
+        .map(|observation| {
+            (observation.date_observation - observations[0].date_observation).num_minutes() as f64
+                / 1440.0
+        })
+        .collect();
+    fill_gaps_at(positions.as_slice(), &mut values, strategy, max_gap);
+    implausible
+        .into_iter()
+        .map(|implausible| {
+            let observation = &mut observations[implausible.index];
+            let replacement = values[implausible.index].map(|value| value.round() as u32);
+            if let Some(replacement) = replacement {
+                observation.value = DataRecording::Recording(replacement);
+                observation.data_flag = None;
+                observation.provenance = Provenance::Imputed;
+            }
+            Replacement {
+                station_id: observation.station_id.clone(),
+                date: implausible.date,
+                issue: implausible.issue,
+                original: implausible.value,
+                replacement,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_implausible, replace_implausible, Issue, QualityChecks};
+    use crate::{
+        gap_fill::Linear,
+        observation::{DataRecording, Duration, Observation, Provenance},
+    };
+    use chrono::NaiveDate;
+
+    fn observations(values: &[u32]) -> Vec<Observation> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(day, value)| {
+                let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap()
+                    + chrono::Duration::days(day as i64);
+                Observation::fixture(
+                    "SHA",
+                    Duration::Daily,
+                    date,
+                    DataRecording::Recording(*value),
+                )
+            })
+            .collect()
+    }
+
+    fn issues(values: &[u32], capacity: i32) -> Vec<(usize, Issue)> {
+        find_implausible(&observations(values), capacity, &QualityChecks::default())
+            .into_iter()
+            .map(|value| (value.index, value.issue))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_implausible() {
+        // an order of magnitude for a day, and the day after is fine
+        assert_eq!(
+            issues(&[1000, 1010, 10100, 1020, 1030], 2000),
+            vec![(2, Issue::AboveCapacity)]
+        );
+        assert_eq!(
+            issues(&[1000, 1010, 1500, 1020, 1030], 2000),
+            vec![(2, Issue::Jump)]
+        );
+        assert_eq!(
+            issues(&[1000, 1010, 960, 1010, 1000], 2000),
+            vec![(2, Issue::Dip)]
+        );
+        // a steady drawdown is no dip
+        assert!(issues(&[1000, 960, 920, 880, 840], 2000).is_empty());
+        // without a capacity, changes are relative to the previous value
+        assert_eq!(issues(&[100, 100, 250, 101], 0), vec![(2, Issue::Jump)]);
+        // a one day jump that does not last, before and after a long run
+        let jump: Vec<u32> = (0..40)
+            .map(|day| match day {
+                0..=4 => 900 + day,
+                5 => 500,
+                _ => 905 + day,
+            })
+            .collect();
+        assert_eq!(issues(&jump, 2000), vec![(5, Issue::Jump)]);
+        // storage that really did step down, and stays there
+        let shifted: Vec<u32> = (0..40)
+            .map(|day| if day < 5 { 900 + day } else { 500 })
+            .collect();
+        assert!(issues(&shifted, 2000).is_empty());
+        let stuck: Vec<u32> = (0..40)
+            .map(|day| if day < 5 { 900 + day } else { 904 })
+            .collect();
+        let stuck_issues = issues(&stuck, 2000);
+        assert_eq!(stuck_issues[0], (5, Issue::Stuck));
+        assert_eq!(stuck_issues.len(), 35);
+    }
+
+    #[test]
+    fn test_replace_implausible() {
+        let mut observations = observations(&[1000, 1010, 10100, 1030, 1040]);
+        observations[4].value = DataRecording::Dash;
+        let replacements = replace_implausible(
+            &mut observations,
+            2000,
+            &QualityChecks::default(),
+            &Linear,
+            None,
+        );
+        assert_eq!(replacements.len(), 1);
+        assert_eq!(replacements[0].original, 10100);
+        assert_eq!(replacements[0].replacement, Some(1020));
+        assert_eq!(
+            replacements[0].to_string(),
+            "SHA 2022-01-03 above capacity: 10100 replaced with 1020"
+        );
+        assert_eq!(observations[2].value, DataRecording::Recording(1020));
+        assert_eq!(observations[2].provenance, Provenance::Imputed);
+        // other gaps are not the checks' business
+        assert_eq!(observations[4].value, DataRecording::Dash);
+        assert_eq!(observations[3].provenance, Provenance::Measured);
+    }
+}
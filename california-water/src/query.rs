@@ -0,0 +1,291 @@
+use crate::{
+    bundle::DatasetBundle,
+    error::BundleError,
+    observation::{Duration, Observation, SensorValue},
+    reservoir::Reservoir,
+    sensor::Sensor,
+    source::CdecSource,
+    statewide::ReservoirObservations,
+    stream::{ObservationFilter, ObservationStream},
+};
+use chrono::NaiveDate;
+use futures::future::join_all;
+use std::collections::{HashMap, HashSet};
+
+/// Selects reservoirs and a date range to fetch, either from CDEC or from
+/// a snapshot.
+///
+/// Every criterion that is set has to hold for a reservoir to be picked;
+/// rivers and lakes match on any part of the name, ignoring case, so
+/// `river("sacramento")` picks every reservoir on the Sacramento River.
+/// The result is a `ReservoirObservations`, whose `total` is the summed
+/// series and whose `station_values` is one series per station.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReservoirQuery {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub sensor: Sensor,
+    /// Station ids to pick, every station when `None`.
+    pub station_ids: Option<HashSet<String>>,
+    pub rivers: Vec<String>,
+    pub lakes: Vec<String>,
+    /// Smallest capacity in acre feet, inclusive.
+    pub min_capacity: Option<i32>,
+    /// Largest capacity in acre feet, inclusive.
+    pub max_capacity: Option<i32>,
+}
+
+impl ReservoirQuery {
+    pub fn new(start_date: NaiveDate, end_date: NaiveDate) -> Self {
+        ReservoirQuery {
+            start_date,
+            end_date,
+            sensor: Sensor::storage(),
+            station_ids: None,
+            rivers: Vec::new(),
+            lakes: Vec::new(),
+            min_capacity: None,
+            max_capacity: None,
+        }
+    }
+
+    pub fn sensor(&mut self, sensor: Sensor) -> &mut Self {
+        self.sensor = sensor;
+        self
+    }
+
+    pub fn station(&mut self, station_id: &str) -> &mut Self {
+        self.station_ids
+            .get_or_insert_with(HashSet::new)
+            .insert(station_id.to_uppercase());
+        self
+    }
+
+    pub fn river(&mut self, river: &str) -> &mut Self {
+        self.rivers.push(river.to_lowercase());
+        self
+    }
+
+    pub fn lake(&mut self, lake: &str) -> &mut Self {
+        self.lakes.push(lake.to_lowercase());
+        self
+    }
+
+    pub fn min_capacity(&mut self, min_capacity: i32) -> &mut Self {
+        self.min_capacity = Some(min_capacity);
+        self
+    }
+
+    pub fn max_capacity(&mut self, max_capacity: i32) -> &mut Self {
+        self.max_capacity = Some(max_capacity);
+        self
+    }
+
+    pub fn matches(&self, reservoir: &Reservoir) -> bool {
+        let names_match = |names: &Vec<String>, name: &str| {
+            let name = name.to_lowercase();
+            names.is_empty() || names.iter().any(|wanted| name.contains(wanted.as_str()))
+        };
+        if let Some(station_ids) = &self.station_ids {
+            if !station_ids.contains(&reservoir.station_id) {
+                return false;
+            }
+        }
+        names_match(&self.rivers, &reservoir.stream)
+            && names_match(&self.lakes, &reservoir.lake)
+            && !matches!(self.min_capacity, Some(min) if reservoir.capacity < min)
+            && !matches!(self.max_capacity, Some(max) if max < reservoir.capacity)
+    }
+
+    /// The reservoirs of `capacity.csv` the query picks.
+    pub fn reservoirs(&self) -> Vec<Reservoir> {
+        Reservoir::get_reservoir_vector()
+            .into_iter()
+            .filter(|reservoir| self.matches(reservoir))
+            .collect()
+    }
+
+    /// Fetches the picked reservoirs from CDEC, daily observations filled
+    /// in from monthly ones as `Observation::get_observations` does. `V`
+    /// is the type of the sensor's values, see `SensorValue`.
+    pub async fn fetch<V: SensorValue>(&self, source: &dyn CdecSource) -> ReservoirObservations<V> {
+        let reservoirs = self.reservoirs();
+        let all_reservoir_observations = join_all(reservoirs.iter().map(|reservoir| {
+            Observation::get_observations(
+                source,
+                reservoir.station_id.as_str(),
+                &self.sensor,
+                &self.start_date,
+                &self.end_date,
+            )
+        }))
+        .await;
+        ReservoirObservations::from_stations(
+            reservoirs
+                .into_iter()
+                .zip(all_reservoir_observations)
+                .collect(),
+        )
+    }
+
+    /// Reads the picked reservoirs from an xz compressed snapshot, filling
+    /// daily observations in from monthly ones the same way `fetch` does.
+    /// Reservoirs the snapshot has nothing for get an empty series.
+    pub fn read_archive(&self, input: &[u8]) -> Result<ReservoirObservations, BundleError> {
+        let reservoirs = self.reservoirs();
+        let mut filter = ObservationFilter::new();
+        filter.start_date(self.start_date).end_date(self.end_date);
+        for reservoir in reservoirs.iter() {
+            filter.station(reservoir.station_id.as_str());
+        }
+        // (daily, monthly) observations of every station
+        let mut by_station: HashMap<String, (Vec<Observation>, Vec<Observation>)> = HashMap::new();
+        for observation in ObservationStream::new(input, filter)? {
+            let observation = observation?;
+            if observation.sensor.number != self.sensor.number {
+                continue;
+            }
+            let (daily, monthly) = by_station
+                .entry(observation.station_id.clone())
+                .or_default();
+            match observation.duration {
+                Duration::Daily => daily.push(observation),
+                Duration::Monthly => monthly.push(observation),
+                _ => {}
+            }
+        }
+        Ok(ReservoirObservations::new(
+            reservoirs
+                .into_iter()
+                .map(|reservoir| {
+                    let (daily, monthly) =
+                        by_station.remove(&reservoir.station_id).unwrap_or_default();
+                    let observations = Observation::fill_from_monthly_observations(daily, monthly);
+                    (reservoir, Ok(observations))
+                })
+                .collect(),
+        ))
+    }
+
+    /// `read_archive` over the current snapshot, see
+    /// `DatasetBundle::current_xz`.
+    pub fn read_current_archive(&self) -> Result<ReservoirObservations, BundleError> {
+        self.read_archive(&DatasetBundle::current_xz()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReservoirQuery;
+    use crate::{reservoir::Reservoir, source::FixtureSource};
+    use chrono::NaiveDate;
+    static BLOCKS_TEST_OBJECT: &[u8] = include_bytes!("../test-fixtures/blocks.tar.xz");
+
+    const SHA_DAILY: &str =
+        "STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+SHA,D,15,STORAGE,20220101 0000,20220101 0000,100, ,AF
+SHA,D,15,STORAGE,20220102 0000,20220102 0000,110, ,AF
+";
+    const KES_DAILY: &str =
+        "STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+KES,D,15,STORAGE,20220101 0000,20220101 0000,5, ,AF
+KES,D,15,STORAGE,20220102 0000,20220102 0000,6, ,AF
+";
+    const MONTHLY: &str =
+        "STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+";
+
+    fn sacramento_river(start_date: NaiveDate, end_date: NaiveDate) -> ReservoirQuery {
+        let mut query = ReservoirQuery::new(start_date, end_date);
+        query.river("sacramento").min_capacity(1_000);
+        query
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let station_ids: Vec<String> = sacramento_river(date, date)
+            .reservoirs()
+            .into_iter()
+            .map(|reservoir| reservoir.station_id)
+            .collect();
+        assert_eq!(station_ids, vec!["SHA", "KES"]);
+        let mut query = ReservoirQuery::new(date, date);
+        query.station("sha").station("ORO").max_capacity(4_000_000);
+        let reservoirs: Vec<Reservoir> = query.reservoirs();
+        assert_eq!(reservoirs.len(), 1);
+        assert_eq!(reservoirs[0].lake, "Lake Oroville");
+        let mut query = ReservoirQuery::new(date, date);
+        query.lake("lake mead");
+        assert_eq!(query.reservoirs()[0].station_id, "MEA");
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_query_fetch() {
+        let mut source = FixtureSource::new();
+        source
+            .insert("SHA", "D", SHA_DAILY)
+            .insert("SHA", "M", MONTHLY)
+            .insert("KES", "D", KES_DAILY)
+            .insert("KES", "M", MONTHLY);
+        let start_date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let end_date = NaiveDate::from_ymd_opt(2022, 1, 2).unwrap();
+        let reservoir_observations = sacramento_river(start_date, end_date)
+            .fetch::<u32>(&source)
+            .await;
+        assert!(reservoir_observations.is_complete());
+        let station_values = reservoir_observations.station_values();
+        assert_eq!(station_values.len(), 2);
+        assert_eq!(station_values["KES"].get(&end_date), Some(&6));
+        let total = reservoir_observations.total();
+        assert_eq!(total.values.get(&start_date), Some(&105));
+        assert_eq!(total.values.get(&end_date), Some(&116));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_query_fetch_monthly_failure() {
+        // KES has no monthly values to give, its daily ones are kept
+        let mut source = FixtureSource::new();
+        source
+            .insert("SHA", "D", SHA_DAILY)
+            .insert("SHA", "M", MONTHLY)
+            .insert("KES", "D", KES_DAILY);
+        let start_date = NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let end_date = NaiveDate::from_ymd_opt(2022, 1, 2).unwrap();
+        let reservoir_observations = sacramento_river(start_date, end_date)
+            .fetch::<u32>(&source)
+            .await;
+        assert!(reservoir_observations.is_complete());
+        assert_eq!(reservoir_observations.series.len(), 2);
+        assert_eq!(reservoir_observations.monthly_failures.len(), 1);
+        assert_eq!(
+            reservoir_observations.monthly_failures[0]
+                .reservoir
+                .station_id,
+            "KES"
+        );
+    }
+
+    #[test]
+    fn test_query_read_archive() {
+        let start_date = NaiveDate::from_ymd_opt(2021, 12, 25).unwrap();
+        let end_date = NaiveDate::from_ymd_opt(2022, 1, 5).unwrap();
+        let mut query = ReservoirQuery::new(start_date, end_date);
+        query.station("SHA").station("FOL");
+        let reservoir_observations = query.read_archive(BLOCKS_TEST_OBJECT).unwrap();
+        assert_eq!(reservoir_observations.series.len(), 2);
+        let station_values = reservoir_observations.station_values();
+        let total = reservoir_observations.total();
+        for (date, value) in total.values.iter() {
+            assert!(start_date <= *date && *date <= end_date);
+            let summed: u32 = station_values
+                .values()
+                .filter_map(|values| values.get(date))
+                .sum();
+            assert_eq!(*value, summed);
+        }
+        assert!(!total.values.is_empty());
+    }
+}
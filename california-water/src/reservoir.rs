@@ -1,9 +1,10 @@
 use csv::ReaderBuilder;
+use serde::Serialize;
 use std::include_str;
 
 static CSV_OBJECT: &str = include_str!("../obj/capacity.csv");
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct Reservoir {
     pub station_id: String,
     pub dam: String,
@@ -0,0 +1,306 @@
+use crate::source::{CdecRequest, CdecSource, SourceError};
+use async_lock::Semaphore;
+use async_trait::async_trait;
+use futures_timer::Delay;
+use instant::Instant;
+use std::{sync::Mutex, time::Duration};
+
+/// How hard a `ScheduledSource` may push its host.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FetchSchedule {
+    /// Requests allowed to be waiting on the host at once.
+    pub max_in_flight: usize,
+    /// How many times a transient failure is retried before giving up.
+    pub max_retries: u32,
+    /// The wait before the first retry, doubled on every retry after it.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Cap on requests started per second, `None` for no cap.
+    pub requests_per_second: Option<f64>,
+}
+
+impl FetchSchedule {
+    pub fn new() -> Self {
+        FetchSchedule::default()
+    }
+
+    pub fn max_in_flight(&mut self, max_in_flight: usize) -> &mut Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    pub fn max_retries(&mut self, max_retries: u32) -> &mut Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn initial_backoff(&mut self, initial_backoff: Duration) -> &mut Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn max_backoff(&mut self, max_backoff: Duration) -> &mut Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn requests_per_second(&mut self, requests_per_second: Option<f64>) -> &mut Self {
+        self.requests_per_second = requests_per_second;
+        self
+    }
+
+    /// The wait before retry number `attempt`, counting from zero.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for FetchSchedule {
+    fn default() -> Self {
+        FetchSchedule {
+            max_in_flight: 8,
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            requests_per_second: Some(10.0),
+        }
+    }
+}
+
+/// Wraps a `CdecSource` so that every request through it follows a
+/// `FetchSchedule`.
+///
+/// A source talks to a single host, so wrapping it once and sharing the
+/// wrapper between every fetch gives a per-host limit no matter how many
+/// stations or date ranges are requested at the same time.
+#[derive(Debug)]
+pub struct ScheduledSource<S> {
+    pub source: S,
+    pub schedule: FetchSchedule,
+    in_flight: Semaphore,
+    next_start: Mutex<Option<Instant>>,
+}
+
+impl<S: CdecSource> ScheduledSource<S> {
+    pub fn new(source: S, schedule: FetchSchedule) -> Self {
+        let in_flight = Semaphore::new(schedule.max_in_flight.max(1));
+        ScheduledSource {
+            source,
+            schedule,
+            in_flight,
+            next_start: Mutex::new(None),
+        }
+    }
+
+    // waits until the rate cap allows another request to start
+    async fn wait_for_turn(&self) {
+        let interval = match self.schedule.requests_per_second {
+            Some(rate) if rate > 0.0 => Duration::from_secs_f64(1.0 / rate),
+            _ => return,
+        };
+        let now = Instant::now();
+        let start = {
+            let mut next_start = self.next_start.lock().unwrap();
+            let start = match *next_start {
+                Some(next) if next > now => next,
+                _ => now,
+            };
+            *next_start = Some(start + interval);
+            start
+        };
+        if start > now {
+            Delay::new(start - now).await;
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: CdecSource> CdecSource for ScheduledSource<S> {
+    async fn get_csv(&self, request: &CdecRequest) -> Result<String, SourceError> {
+        let mut attempt = 0;
+        loop {
+            let permit = self.in_flight.acquire().await;
+            self.wait_for_turn().await;
+            match self.source.get_csv(request).await {
+                Err(e) if e.is_transient() && attempt < self.schedule.max_retries => {
+                    // let other requests go ahead while this one backs off
+                    drop(permit);
+                    Delay::new(self.schedule.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FetchSchedule, ScheduledSource};
+    use crate::source::{CdecRequest, CdecSource, ReqwestSource, SourceError};
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+    use futures::future::join_all;
+    use futures_timer::Delay;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::Mutex,
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    fn request(station_id: &str) -> CdecRequest {
+        CdecRequest::new(
+            station_id,
+            15,
+            "D",
+            &NaiveDate::from_ymd_opt(2022, 2, 15).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 17).unwrap(),
+        )
+    }
+
+    // answers every request after a short wait, remembering how many were
+    // being answered at the same time
+    #[derive(Default)]
+    struct SlowSource {
+        // (in flight now, most in flight ever)
+        in_flight: Mutex<(usize, usize)>,
+    }
+
+    #[async_trait(?Send)]
+    impl CdecSource for SlowSource {
+        async fn get_csv(&self, request: &CdecRequest) -> Result<String, SourceError> {
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            Delay::new(Duration::from_millis(10)).await;
+            self.in_flight.lock().unwrap().0 -= 1;
+            Ok(request.station_id.clone())
+        }
+    }
+
+    // answers one connection per response in order, handing back how many
+    // it answered
+    fn serve_sequence(responses: Vec<&'static str>) -> (String, JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for status_line in responses.iter() {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).unwrap();
+                assert!(n > 0);
+                let body = "STATION_ID,DURATION\n";
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status_line,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            responses.len()
+        });
+        (format!("http://{}/servlet", address), server)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut schedule = FetchSchedule::new();
+        schedule
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(500));
+        assert_eq!(schedule.backoff(0), Duration::from_millis(100));
+        assert_eq!(schedule.backoff(2), Duration::from_millis(400));
+        assert_eq!(schedule.backoff(3), Duration::from_millis(500));
+        assert_eq!(schedule.backoff(40), Duration::from_millis(500));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        let mut schedule = FetchSchedule::new();
+        schedule.max_in_flight(2).requests_per_second(None);
+        let source = ScheduledSource::new(SlowSource::default(), schedule);
+        let requests: Vec<CdecRequest> = (0..6).map(|i| request(&format!("S{}", i))).collect();
+        let bodies = join_all(requests.iter().map(|r| source.get_csv(r))).await;
+        assert!(bodies.iter().all(|body| body.is_ok()));
+        assert_eq!(source.source.in_flight.lock().unwrap().1, 2);
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let mut schedule = FetchSchedule::new();
+        schedule.max_in_flight(10).requests_per_second(Some(50.0));
+        let source = ScheduledSource::new(SlowSource::default(), schedule);
+        let requests: Vec<CdecRequest> = (0..5).map(|i| request(&format!("S{}", i))).collect();
+        let start = Instant::now();
+        join_all(requests.iter().map(|r| source.get_csv(r))).await;
+        // five starts 20ms apart
+        assert!(start.elapsed() >= Duration::from_millis(80));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        let (base_url, server) = serve_sequence(vec![
+            "503 Service Unavailable",
+            "429 Too Many Requests",
+            "200 OK",
+        ]);
+        let mut schedule = FetchSchedule::new();
+        schedule.initial_backoff(Duration::from_millis(1));
+        let source =
+            ScheduledSource::new(ReqwestSource::with_base_url(base_url.as_str()), schedule);
+        let body = source.get_csv(&request("VIL")).await.unwrap();
+        assert_eq!(body, "STATION_ID,DURATION\n");
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_backoff_releases_slot() {
+        let (base_url, server) =
+            serve_sequence(vec!["503 Service Unavailable", "200 OK", "200 OK"]);
+        let mut schedule = FetchSchedule::new();
+        schedule
+            .max_in_flight(1)
+            .requests_per_second(None)
+            .initial_backoff(Duration::from_millis(200));
+        let source =
+            ScheduledSource::new(ReqwestSource::with_base_url(base_url.as_str()), schedule);
+        let (first, second) = (request("VIL"), request("SHA"));
+        let finished = join_all([&first, &second].iter().map(|r| async {
+            source.get_csv(r).await.unwrap();
+            Instant::now()
+        }))
+        .await;
+        // the second request was answered while the first backed off
+        assert!(finished[1] < finished[0]);
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let (base_url, server) =
+            serve_sequence(vec!["503 Service Unavailable", "503 Service Unavailable"]);
+        let mut schedule = FetchSchedule::new();
+        schedule
+            .max_retries(1)
+            .initial_backoff(Duration::from_millis(1));
+        let source =
+            ScheduledSource::new(ReqwestSource::with_base_url(base_url.as_str()), schedule);
+        let error = source.get_csv(&request("VIL")).await.unwrap_err();
+        assert_eq!(error.status(), Some(503));
+        assert_eq!(server.join().unwrap(), 2);
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+/// A CDEC sensor: the `SENSOR_NUMBER`, `SENSOR_TYPE` and `UNITS`
+/// columns of a `CSVDataServlet` row.
+///
+/// The well known sensors have constructors, anything else can be
+/// built with `Sensor::new` or looked up with `Sensor::from_number`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize)]
+pub struct Sensor {
+    pub number: u32,
+    pub kind: String,
+    pub units: String,
+}
+
+// (number, SENSOR_TYPE, UNITS) as CDEC reports them
+const KNOWN_SENSORS: [(u32, &str, &str); 8] = [
+    (2, "RAIN", "INCHES"),
+    (3, "SNOW WC", "INCHES"),
+    (6, "RES ELE", "FEET"),
+    (15, "STORAGE", "AF"),
+    (23, "OUTFLOW", "CFS"),
+    (45, "PPT INC", "INCHES"),
+    (76, "INFLOW", "CFS"),
+    (82, "SNO ADJ", "INCHES"),
+];
+
+impl Sensor {
+    pub fn new(number: u32, kind: &str, units: &str) -> Self {
+        Sensor {
+            number,
+            kind: String::from(kind),
+            units: String::from(units),
+        }
+    }
+
+    /// Looks up a sensor number in the sensors we know about, falling back
+    /// to a sensor with an empty type and units.
+    pub fn from_number(number: u32) -> Self {
+        match KNOWN_SENSORS.iter().find(|(n, _, _)| *n == number) {
+            Some((n, kind, units)) => Sensor::new(*n, kind, units),
+            None => Sensor::new(number, "", ""),
+        }
+    }
+
+    pub fn storage() -> Self {
+        Sensor::from_number(15)
+    }
+
+    pub fn reservoir_elevation() -> Self {
+        Sensor::from_number(6)
+    }
+
+    pub fn inflow() -> Self {
+        Sensor::from_number(76)
+    }
+
+    pub fn outflow() -> Self {
+        Sensor::from_number(23)
+    }
+
+    pub fn precipitation_accumulated() -> Self {
+        Sensor::from_number(2)
+    }
+
+    pub fn precipitation_incremental() -> Self {
+        Sensor::from_number(45)
+    }
+
+    pub fn snow_water_content() -> Self {
+        Sensor::from_number(3)
+    }
+
+    pub fn snow_water_content_revised() -> Self {
+        Sensor::from_number(82)
+    }
+}
+
+impl Default for Sensor {
+    fn default() -> Self {
+        Sensor::storage()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Sensor;
+
+    #[test]
+    fn test_from_number() {
+        assert_eq!(Sensor::from_number(15), Sensor::new(15, "STORAGE", "AF"));
+        assert_eq!(Sensor::from_number(6), Sensor::reservoir_elevation());
+        assert_eq!(Sensor::inflow().units, "CFS");
+        assert_eq!(Sensor::from_number(999), Sensor::new(999, "", ""));
+    }
+}
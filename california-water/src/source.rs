@@ -0,0 +1,323 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::Client;
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+pub const CDEC_BASE_URL: &str = "http://cdec.water.ca.gov/dynamicapp/req/CSVDataServlet";
+const YEAR_FORMAT: &str = "%Y-%m-%d";
+const OBS_DATE_FORMAT: &str = "%Y%m%d";
+
+#[derive(Debug)]
+pub enum SourceError {
+    Http(reqwest::Error),
+    MissingFixture(String),
+}
+
+impl SourceError {
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            SourceError::Http(e) => e.status().map(|status| status.as_u16()),
+            SourceError::MissingFixture(..) => None,
+        }
+    }
+
+    /// Whether trying the same request again could succeed: timeouts,
+    /// dropped connections, 429 and 5xx answers.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SourceError::Http(e) => match e.status() {
+                Some(status) => status.as_u16() == 429 || status.is_server_error(),
+                None => e.is_timeout() || e.is_request() || e.is_body(),
+            },
+            SourceError::MissingFixture(..) => false,
+        }
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Http(e) => write!(f, "http request failed: {}", e),
+            SourceError::MissingFixture(key) => write!(f, "no fixture for {}", key),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SourceError::Http(e) => Some(e),
+            SourceError::MissingFixture(..) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SourceError {
+    fn from(e: reqwest::Error) -> Self {
+        SourceError::Http(e)
+    }
+}
+
+/// The parameters of a single `CSVDataServlet` call.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CdecRequest {
+    pub station_id: String,
+    pub sensor_number: u32,
+    pub dur_code: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+}
+
+impl CdecRequest {
+    pub fn new(
+        station_id: &str,
+        sensor_number: u32,
+        dur_code: &str,
+        start_date: &NaiveDate,
+        end_date: &NaiveDate,
+    ) -> Self {
+        CdecRequest {
+            station_id: String::from(station_id),
+            sensor_number,
+            dur_code: String::from(dur_code),
+            start_date: *start_date,
+            end_date: *end_date,
+        }
+    }
+
+    pub fn url(&self, base_url: &str) -> String {
+        format!(
+            "{}?Stations={}&SensorNums={}&dur_code={}&Start={}&End={}",
+            base_url,
+            self.station_id,
+            self.sensor_number,
+            self.dur_code,
+            self.start_date.format(YEAR_FORMAT),
+            self.end_date.format(YEAR_FORMAT)
+        )
+    }
+}
+
+/// Anything that can answer a `CSVDataServlet` request with CDEC's csv body.
+///
+/// `ReqwestSource` talks to CDEC (or anything pretending to be CDEC at
+/// another base url) and `FixtureSource` answers from memory so the
+/// fetching pipeline can run offline.
+#[async_trait(?Send)]
+pub trait CdecSource {
+    async fn get_csv(&self, request: &CdecRequest) -> Result<String, SourceError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ReqwestSource {
+    pub client: Client,
+    pub base_url: String,
+}
+
+impl ReqwestSource {
+    pub fn new() -> Self {
+        ReqwestSource::with_base_url(CDEC_BASE_URL)
+    }
+
+    pub fn with_base_url(base_url: &str) -> Self {
+        ReqwestSource {
+            client: Client::new(),
+            base_url: String::from(base_url),
+        }
+    }
+}
+
+impl Default for ReqwestSource {
+    fn default() -> Self {
+        ReqwestSource::new()
+    }
+}
+
+#[async_trait(?Send)]
+impl CdecSource for ReqwestSource {
+    async fn get_csv(&self, request: &CdecRequest) -> Result<String, SourceError> {
+        let url = request.url(self.base_url.as_str());
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        Ok(response.text().await?)
+    }
+}
+
+/// Serves canned csv bodies keyed by station id and duration code.
+///
+/// Rows for other sensors or outside of the requested date range are
+/// dropped, the same as CDEC would do, and every request is recorded so
+/// tests can check what was asked for.
+#[derive(Debug, Default)]
+pub struct FixtureSource {
+    pub fixtures: HashMap<(String, String), String>,
+    pub requests: Mutex<Vec<CdecRequest>>,
+}
+
+impl FixtureSource {
+    pub fn new() -> Self {
+        FixtureSource::default()
+    }
+
+    pub fn insert(&mut self, station_id: &str, dur_code: &str, body: &str) -> &mut Self {
+        self.fixtures.insert(
+            (station_id.to_uppercase(), String::from(dur_code)),
+            String::from(body),
+        );
+        self
+    }
+
+    pub fn requests(&self) -> Vec<CdecRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl CdecSource for FixtureSource {
+    async fn get_csv(&self, request: &CdecRequest) -> Result<String, SourceError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let key = (request.station_id.to_uppercase(), request.dur_code.clone());
+        let body = match self.fixtures.get(&key) {
+            Some(body) => body,
+            None => return Err(SourceError::MissingFixture(format!("{} {}", key.0, key.1))),
+        };
+        let sensor_number = request.sensor_number.to_string();
+        let start = request.start_date.format(OBS_DATE_FORMAT).to_string();
+        let end = request.end_date.format(OBS_DATE_FORMAT).to_string();
+        // keep the header, then any row of the sensor whose OBS DATE falls in range
+        let mut lines = body.lines();
+        let mut output = String::new();
+        if let Some(header) = lines.next() {
+            output.push_str(header);
+            output.push('\n');
+        }
+        for line in lines {
+            let columns: Vec<&str> = line.split(',').collect();
+            let sensor = columns.get(2).cloned().unwrap_or("");
+            let day = columns.get(5).and_then(|d| d.get(0..8)).unwrap_or("");
+            if sensor == sensor_number && day >= start.as_str() && day <= end.as_str() {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CdecRequest, CdecSource, FixtureSource, ReqwestSource, SourceError};
+    use chrono::NaiveDate;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    const STR_RESULT: &str = r#"STATION_ID,DURATION,SENSOR_NUMBER,SENSOR_TYPE,DATE TIME,OBS DATE,VALUE,DATA_FLAG,UNITS
+VIL,D,15,STORAGE,20220215 0000,20220215 0000,9593, ,AF
+VIL,D,15,STORAGE,20220216 0000,20220216 0000,9589, ,AF
+VIL,D,15,STORAGE,20220217 0000,20220217 0000,9589, ,AF
+VIL,D,6,RES ELE,20220217 0000,20220217 0000,1420.5, ,FEET
+"#;
+
+    #[test]
+    fn test_request_url() {
+        let request = CdecRequest::new(
+            "VIL",
+            6,
+            "D",
+            &NaiveDate::from_ymd_opt(2022, 2, 15).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 28).unwrap(),
+        );
+        assert_eq!(
+            request.url("http://localhost:8000/servlet"),
+            "http://localhost:8000/servlet?Stations=VIL&SensorNums=6&dur_code=D&Start=2022-02-15&End=2022-02-28"
+        );
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_fixture_source() {
+        let mut source = FixtureSource::new();
+        source.insert("VIL", "D", STR_RESULT);
+        let request = CdecRequest::new(
+            "VIL",
+            15,
+            "D",
+            &NaiveDate::from_ymd_opt(2022, 2, 16).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 28).unwrap(),
+        );
+        let body = source.get_csv(&request).await.unwrap();
+        assert_eq!(body.lines().count(), 3);
+        assert!(!body.contains("20220215"));
+        assert!(!body.contains("RES ELE"));
+        assert_eq!(source.requests(), vec![request]);
+        let missing = CdecRequest::new(
+            "SHA",
+            15,
+            "D",
+            &NaiveDate::from_ymd_opt(2022, 2, 16).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 28).unwrap(),
+        );
+        assert!(matches!(
+            source.get_csv(&missing).await,
+            Err(SourceError::MissingFixture(..))
+        ));
+    }
+
+    // answers a single http request with `status_line` and `body`, handing
+    // back the request line it saw
+    fn serve_once(status_line: &'static str, body: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).unwrap();
+            let request_line = String::from_utf8_lossy(&buf[..n]).to_string();
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status_line,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            request_line
+        });
+        (format!("http://{}/servlet", address), server)
+    }
+
+    fn vil_request() -> CdecRequest {
+        CdecRequest::new(
+            "VIL",
+            15,
+            "D",
+            &NaiveDate::from_ymd_opt(2022, 2, 15).unwrap(),
+            &NaiveDate::from_ymd_opt(2022, 2, 17).unwrap(),
+        )
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_reqwest_source_against_local_server() {
+        let (base_url, server) = serve_once("200 OK", STR_RESULT);
+        let source = ReqwestSource::with_base_url(base_url.as_str());
+        let body = source.get_csv(&vil_request()).await.unwrap();
+        assert_eq!(body, STR_RESULT);
+        let request_line = server.join().unwrap();
+        assert!(request_line.starts_with(
+            "GET /servlet?Stations=VIL&SensorNums=15&dur_code=D&Start=2022-02-15&End=2022-02-17 "
+        ));
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn test_reqwest_source_error_status() {
+        let (base_url, server) = serve_once("503 Service Unavailable", "busy");
+        let source = ReqwestSource::with_base_url(base_url.as_str());
+        let error = source.get_csv(&vil_request()).await.unwrap_err();
+        assert_eq!(error.status(), Some(503));
+        assert!(error.is_transient());
+        server.join().unwrap();
+    }
+}
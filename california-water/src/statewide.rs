@@ -0,0 +1,450 @@
+use crate::{
+    error::ObservationError,
+    fill::{daily_fill, DailyFill},
+    gap_fill::GapFill,
+    observation::{DataRecording, Observation, Provenance, SensorValue, StationObservations},
+    quality::{
+        find_implausible, replace_implausible, ImplausibleValue, QualityChecks, QualityReport,
+    },
+    reservoir::Reservoir,
+    water_year::{summarize_water_years, WaterYear, WaterYearSummary},
+};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+/// Every observation fetched for one reservoir, storage unless `V` says
+/// otherwise, see `SensorValue`.
+#[derive(Debug, Clone)]
+pub struct StationSeries<V = u32> {
+    pub reservoir: Reservoir,
+    pub observations: Vec<Observation<V>>,
+}
+
+impl StationSeries {
+    /// The daily values of the series, non-recordings left out.
+    pub fn values(&self) -> BTreeMap<NaiveDate, u32> {
+        self.observations
+            .iter()
+            .filter_map(|observation| match observation.value {
+                DataRecording::Recording(value) => {
+                    Some((observation.date_observation.date(), value))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Where each of the daily values of the series came from.
+    pub fn provenance(&self) -> BTreeMap<NaiveDate, Provenance> {
+        self.observations
+            .iter()
+            .filter(|observation| matches!(observation.value, DataRecording::Recording(..)))
+            .map(|observation| (observation.date_observation.date(), observation.provenance))
+            .collect()
+    }
+
+    /// The values of the series that fail `checks`, see `quality`.
+    pub fn implausible(&self, checks: &QualityChecks) -> Vec<ImplausibleValue> {
+        find_implausible(&self.observations, self.reservoir.capacity, checks)
+    }
+
+    /// How full the reservoir was on every day of the series.
+    pub fn fill(&self) -> Vec<DailyFill> {
+        daily_fill(&self.values(), self.reservoir.capacity)
+    }
+
+    /// Minimum, maximum, mean and carryover storage of every water year
+    /// of the series.
+    pub fn water_years(&self) -> BTreeMap<WaterYear, WaterYearSummary> {
+        summarize_water_years(&self.values())
+    }
+}
+
+/// A reservoir that could not be fetched, and why.
+#[derive(Debug)]
+pub struct StationFailure {
+    pub reservoir: Reservoir,
+    pub error: ObservationError,
+}
+
+/// Daily storage summed over the reservoirs that reported.
+///
+/// When some reservoirs failed the total is short by up to
+/// `missing_capacity_fraction` of the statewide capacity and
+/// `is_complete` is false.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StatewideTotal {
+    pub values: BTreeMap<NaiveDate, u32>,
+    pub is_complete: bool,
+    pub missing_capacity_fraction: f64,
+}
+
+impl StatewideTotal {
+    /// Minimum, maximum, mean and carryover statewide storage of every
+    /// water year.
+    pub fn water_years(&self) -> BTreeMap<WaterYear, WaterYearSummary> {
+        summarize_water_years(&self.values)
+    }
+}
+
+/// What fetching one reservoir gave, see `ReservoirObservations::new`.
+pub type StationResult<V = u32> = (Reservoir, Result<Vec<Observation<V>>, ObservationError>);
+
+/// What fetching the daily and monthly observations of one reservoir
+/// gave, see `ReservoirObservations::from_stations`.
+pub type StationObservationsResult<V = u32> =
+    (Reservoir, Result<StationObservations<V>, ObservationError>);
+
+/// The outcome of fetching many reservoirs at once, keeping whatever
+/// succeeded next to the stations that failed.
+///
+/// Totals, fill and quality checks are about storage and need the
+/// default whole acre feet.
+#[derive(Debug)]
+pub struct ReservoirObservations<V = u32> {
+    pub series: Vec<StationSeries<V>>,
+    pub failures: Vec<StationFailure>,
+    /// Reservoirs in `series` whose monthly observations could not be
+    /// fetched, so that only their daily ones are there.
+    pub monthly_failures: Vec<StationFailure>,
+}
+
+impl<V> Default for ReservoirObservations<V> {
+    fn default() -> Self {
+        ReservoirObservations {
+            series: Vec::new(),
+            failures: Vec::new(),
+            monthly_failures: Vec::new(),
+        }
+    }
+}
+
+impl<V: SensorValue> ReservoirObservations<V> {
+    pub fn new(results: Vec<StationResult<V>>) -> Self {
+        let mut reservoir_observations = ReservoirObservations::default();
+        for (reservoir, result) in results {
+            match result {
+                Ok(observations) => reservoir_observations.series.push(StationSeries {
+                    reservoir,
+                    observations,
+                }),
+                Err(error) => reservoir_observations
+                    .failures
+                    .push(StationFailure { reservoir, error }),
+            }
+        }
+        reservoir_observations
+    }
+
+    /// `new`, keeping the monthly errors of the stations that have daily
+    /// observations in `monthly_failures`.
+    pub fn from_stations(results: Vec<StationObservationsResult<V>>) -> Self {
+        let mut monthly_failures = Vec::new();
+        let results = results
+            .into_iter()
+            .map(|(reservoir, result)| {
+                let result = result.map(|station| {
+                    if let Some(error) = station.monthly_error {
+                        monthly_failures.push(StationFailure {
+                            reservoir: reservoir.clone(),
+                            error,
+                        });
+                    }
+                    station.observations
+                });
+                (reservoir, result)
+            })
+            .collect();
+        let mut reservoir_observations = ReservoirObservations::new(results);
+        reservoir_observations.monthly_failures = monthly_failures;
+        reservoir_observations
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn failed_station_ids(&self) -> Vec<&str> {
+        self.failures
+            .iter()
+            .map(|failure| failure.reservoir.station_id.as_str())
+            .collect()
+    }
+
+    /// The share of the total capacity, over every reservoir that was
+    /// asked for, held by the reservoirs that failed.
+    pub fn missing_capacity_fraction(&self) -> f64 {
+        let capacity = |reservoir: &Reservoir| reservoir.capacity.max(0) as i64;
+        let missing: i64 = self
+            .failures
+            .iter()
+            .map(|failure| capacity(&failure.reservoir))
+            .sum();
+        let reported: i64 = self
+            .series
+            .iter()
+            .map(|series| capacity(&series.reservoir))
+            .sum();
+        if missing + reported == 0 {
+            return 0.0;
+        }
+        (missing as f64) / ((missing + reported) as f64)
+    }
+}
+
+impl ReservoirObservations {
+    /// One series of daily values per station id, see
+    /// `StationSeries::values`.
+    pub fn station_values(&self) -> BTreeMap<String, BTreeMap<NaiveDate, u32>> {
+        self.series
+            .iter()
+            .map(|series| (series.reservoir.station_id.clone(), series.values()))
+            .collect()
+    }
+
+    /// Replaces the values of every series that fail `checks` with ones
+    /// filled in by `strategy`, see `quality::replace_implausible`.
+    pub fn replace_implausible(
+        &mut self,
+        checks: &QualityChecks,
+        strategy: &dyn GapFill,
+        max_gap: Option<usize>,
+    ) -> QualityReport {
+        let mut report = QualityReport::default();
+        for series in self.series.iter_mut() {
+            report.replacements.append(&mut replace_implausible(
+                &mut series.observations,
+                series.reservoir.capacity,
+                checks,
+                strategy,
+                max_gap,
+            ));
+        }
+        report
+    }
+
+    /// Where the statewide total of every day came from: measured when
+    /// every reservoir's value was, otherwise the provenance of the first
+    /// value that was not.
+    pub fn total_provenance(&self) -> BTreeMap<NaiveDate, Provenance> {
+        let mut total: BTreeMap<NaiveDate, Provenance> = BTreeMap::new();
+        for series in &self.series {
+            for (date, provenance) in series.provenance() {
+                let day = total.entry(date).or_default();
+                if day.is_measured() {
+                    *day = provenance;
+                }
+            }
+        }
+        total
+    }
+
+    /// Statewide storage as a percent of capacity for every day, counting
+    /// only the capacity of the reservoirs that reported that day.
+    pub fn percent_full(&self) -> BTreeMap<NaiveDate, f64> {
+        // (storage, capacity) of the reservoirs that reported
+        let mut reported: BTreeMap<NaiveDate, (u64, u64)> = BTreeMap::new();
+        for series in &self.series {
+            if series.reservoir.capacity <= 0 {
+                continue;
+            }
+            for (date, storage) in series.values() {
+                let (storage_sum, capacity_sum) = reported.entry(date).or_insert((0, 0));
+                *storage_sum += storage as u64;
+                *capacity_sum += series.reservoir.capacity as u64;
+            }
+        }
+        reported
+            .into_iter()
+            .map(|(date, (storage, capacity))| (date, storage as f64 / capacity as f64 * 100.0))
+            .collect()
+    }
+
+    /// Storage summed by day over the reservoirs with a recording that
+    /// day; days without any recording are left out rather than zero.
+    pub fn total(&self) -> StatewideTotal {
+        let mut values: BTreeMap<NaiveDate, u32> = BTreeMap::new();
+        for series in &self.series {
+            for observation in &series.observations {
+                if let DataRecording::Recording(k) = observation.value {
+                    values
+                        .entry(observation.date_observation.date())
+                        .and_modify(|e| *e += k)
+                        .or_insert(k);
+                }
+            }
+        }
+        StatewideTotal {
+            values,
+            is_complete: self.is_complete(),
+            missing_capacity_fraction: self.missing_capacity_fraction(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReservoirObservations;
+    use crate::{
+        error::ObservationError,
+        gap_fill::Linear,
+        observation::{DataRecording, Duration, Observation, Provenance},
+        quality::QualityChecks,
+        reservoir::Reservoir,
+        source::{CdecRequest, SourceError},
+        water_year::WaterYear,
+    };
+    use chrono::NaiveDate;
+
+    fn reservoir(station_id: &str, capacity: i32) -> Reservoir {
+        Reservoir {
+            station_id: String::from(station_id),
+            dam: String::new(),
+            lake: String::new(),
+            stream: String::new(),
+            capacity,
+            fill_year: 0,
+        }
+    }
+
+    fn observation(station_id: &str, day: u32, value: u32) -> Observation {
+        let date = NaiveDate::from_ymd_opt(2022, 2, day).unwrap();
+        Observation::fixture(
+            station_id,
+            Duration::Daily,
+            date,
+            DataRecording::Recording(value),
+        )
+    }
+
+    #[test]
+    fn test_partial_total() {
+        let date = NaiveDate::from_ymd_opt(2022, 2, 15).unwrap();
+        let error = ObservationError::Request {
+            request: Box::new(CdecRequest::new("ORO", 15, "D", &date, &date)),
+            source: SourceError::MissingFixture(String::from("ORO D")),
+        };
+        let results = vec![
+            (
+                reservoir("SHA", 300),
+                Ok(vec![observation("SHA", 15, 10), observation("SHA", 16, 20)]),
+            ),
+            (reservoir("ORO", 100), Err(error)),
+            (reservoir("VIL", 100), Ok(vec![observation("VIL", 15, 1)])),
+        ];
+        let reservoir_observations = ReservoirObservations::new(results);
+        assert!(!reservoir_observations.is_complete());
+        assert_eq!(reservoir_observations.failed_station_ids(), vec!["ORO"]);
+        let total = reservoir_observations.total();
+        assert!(!total.is_complete);
+        assert_eq!(total.missing_capacity_fraction, 0.2);
+        assert_eq!(total.values.get(&date), Some(&11));
+        assert_eq!(total.values.len(), 2);
+    }
+
+    // SHA reports on the 15th and 16th, ORO fails and VIL reports
+    // `vil` on the 15th only
+    fn partial_observations(vil: Observation) -> ReservoirObservations {
+        let date = NaiveDate::from_ymd_opt(2022, 2, 15).unwrap();
+        let error = ObservationError::Request {
+            request: Box::new(CdecRequest::new("ORO", 15, "D", &date, &date)),
+            source: SourceError::MissingFixture(String::from("ORO D")),
+        };
+        let results = vec![
+            (
+                reservoir("SHA", 300),
+                Ok(vec![observation("SHA", 15, 10), observation("SHA", 16, 20)]),
+            ),
+            (reservoir("ORO", 100), Err(error)),
+            (reservoir("VIL", 100), Ok(vec![vil])),
+        ];
+        ReservoirObservations::new(results)
+    }
+
+    #[test]
+    fn test_percent_full_partial() {
+        let reservoir_observations = partial_observations(observation("VIL", 15, 1));
+        // ORO failed and VIL did not report on the 16th
+        let percent_full = reservoir_observations.percent_full();
+        let date = NaiveDate::from_ymd_opt(2022, 2, 15).unwrap();
+        assert_eq!(percent_full.get(&date), Some(&2.75));
+        let next_day = NaiveDate::from_ymd_opt(2022, 2, 16).unwrap();
+        assert!((percent_full[&next_day] - 20.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_station_fill() {
+        let reservoir_observations = partial_observations(observation("VIL", 15, 1));
+        let fill = reservoir_observations.series[0].fill();
+        assert_eq!(fill[1].drawdown, 0);
+        assert_eq!(fill[1].days_since_full, None);
+    }
+
+    #[test]
+    fn test_total_water_years() {
+        let reservoir_observations = partial_observations(observation("VIL", 15, 1));
+        let water_years = reservoir_observations.total().water_years();
+        assert_eq!(water_years[&WaterYear(2022)].max, 20);
+    }
+
+    #[test]
+    fn test_total_provenance() {
+        let mut imputed = observation("VIL", 15, 1);
+        imputed.provenance = Provenance::Imputed;
+        let reservoir_observations = partial_observations(imputed);
+        // a day is as good as its least certain value
+        let provenance = reservoir_observations.total_provenance();
+        let date = NaiveDate::from_ymd_opt(2022, 2, 15).unwrap();
+        assert_eq!(provenance[&date], Provenance::Imputed);
+        let next_day = NaiveDate::from_ymd_opt(2022, 2, 16).unwrap();
+        assert_eq!(provenance[&next_day], Provenance::Measured);
+    }
+
+    #[test]
+    fn test_replace_implausible() {
+        let results = vec![
+            (
+                reservoir("SHA", 300),
+                Ok(vec![
+                    observation("SHA", 15, 100),
+                    observation("SHA", 16, 3000),
+                    observation("SHA", 17, 110),
+                ]),
+            ),
+            (reservoir("VIL", 100), Ok(vec![observation("VIL", 16, 50)])),
+        ];
+        let mut reservoir_observations = ReservoirObservations::new(results);
+        let checks = QualityChecks::default();
+        assert_eq!(
+            reservoir_observations.series[0].implausible(&checks).len(),
+            1
+        );
+        let report = reservoir_observations.replace_implausible(&checks, &Linear, None);
+        assert_eq!(report.replaced(), 1);
+        assert_eq!(report.replacements[0].station_id, "SHA");
+        let total = reservoir_observations.total();
+        let day = NaiveDate::from_ymd_opt(2022, 2, 16).unwrap();
+        assert_eq!(total.values[&day], 155);
+        assert_eq!(
+            reservoir_observations.total_provenance()[&day],
+            Provenance::Imputed
+        );
+    }
+
+    #[test]
+    fn test_total_leaves_out_missing_days() {
+        let mut invalid = observation("SHA", 17, 0);
+        invalid.value = DataRecording::Invalid;
+        let results = vec![(
+            reservoir("SHA", 300),
+            Ok(vec![observation("SHA", 16, 0), invalid]),
+        )];
+        let total = ReservoirObservations::new(results).total();
+        // a recorded zero counts, a value that is not a number does not
+        let values: Vec<(NaiveDate, u32)> = total.values.into_iter().collect();
+        assert_eq!(
+            values,
+            vec![(NaiveDate::from_ymd_opt(2022, 2, 16).unwrap(), 0)]
+        );
+    }
+}
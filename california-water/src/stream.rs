@@ -0,0 +1,439 @@
+use crate::{
+    bundle::{DatasetBundle, MANIFEST_NAME},
+    compression::XzBlocks,
+    error::BundleError,
+    observation::Observation,
+};
+use chrono::NaiveDate;
+use csv::StringRecord;
+use std::{borrow::Cow, collections::HashSet};
+
+const TAR_BLOCK: usize = 512;
+const OBS_DATE_FORMAT: &str = "%Y%m%d";
+
+/// Which rows of a snapshot to keep.
+///
+/// The filter is checked against the raw csv fields of a row, so rows
+/// that are not wanted are never parsed into an `Observation`.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ObservationFilter {
+    /// Station ids to keep, every station when `None`.
+    pub stations: Option<HashSet<String>>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+impl ObservationFilter {
+    pub fn new() -> Self {
+        ObservationFilter::default()
+    }
+
+    pub fn station(&mut self, station_id: &str) -> &mut Self {
+        self.stations
+            .get_or_insert_with(HashSet::new)
+            .insert(station_id.to_uppercase());
+        self
+    }
+
+    pub fn start_date(&mut self, start_date: NaiveDate) -> &mut Self {
+        self.start_date = Some(start_date);
+        self
+    }
+
+    pub fn end_date(&mut self, end_date: NaiveDate) -> &mut Self {
+        self.end_date = Some(end_date);
+        self
+    }
+
+    /// Whether a row is kept, judged by its station id and observation
+    /// date; rows too short to tell are kept so that parsing reports them.
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        if let (Some(stations), Some(station_id)) = (&self.stations, record.get(0)) {
+            if !stations.contains(station_id) {
+                return false;
+            }
+        }
+        if self.start_date.is_none() && self.end_date.is_none() {
+            return true;
+        }
+        let date = record
+            .get(5)
+            .and_then(|date| date.get(0..8))
+            .and_then(|date| NaiveDate::parse_from_str(date, OBS_DATE_FORMAT).ok());
+        match date {
+            Some(date) => {
+                !matches!(self.start_date, Some(start) if date < start)
+                    && !matches!(self.end_date, Some(end) if end < date)
+            }
+            None => true,
+        }
+    }
+}
+
+// where the stream is in the tar
+#[derive(Debug, Clone)]
+enum TarPosition {
+    Header,
+    Entry {
+        name: String,
+        is_csv: bool,
+        remaining: usize,
+        padding: usize,
+        line: u64,
+    },
+    End,
+}
+
+/// Observations decoded lazily from an xz compressed snapshot.
+///
+/// Only one xz block of the snapshot is held in memory at a time, and each
+/// csv row is checked against an `ObservationFilter` before it is parsed.
+/// Rows are read as plain comma separated fields, as every snapshot writes
+/// them. Each xz block is checked as it is decoded, the checksums of the
+/// manifest are not, see `DatasetBundle::verify`.
+#[derive(Debug, Clone)]
+pub struct ObservationStream<'a> {
+    blocks: XzBlocks<'a>,
+    filter: ObservationFilter,
+    // decoded bytes not consumed yet
+    buffer: Vec<u8>,
+    position: TarPosition,
+    record: StringRecord,
+}
+
+impl<'a> ObservationStream<'a> {
+    pub fn new<I: Into<Cow<'a, [u8]>>>(
+        input: I,
+        filter: ObservationFilter,
+    ) -> Result<Self, BundleError> {
+        Ok(ObservationStream {
+            blocks: XzBlocks::new(input)?,
+            filter,
+            buffer: Vec::new(),
+            position: TarPosition::Header,
+            record: StringRecord::new(),
+        })
+    }
+
+    /// Streams the current snapshot, the one compiled into the crate or
+    /// the one named by `CALIFORNIA_WATER_BUNDLE` when that is newer, see
+    /// `DatasetBundle::current_xz`.
+    pub fn current(filter: ObservationFilter) -> Result<ObservationStream<'static>, BundleError> {
+        ObservationStream::new(DatasetBundle::current_xz()?, filter)
+    }
+
+    // decodes the next block onto the end of the buffer, false once there
+    // are no blocks left
+    fn fill(&mut self, consumed: usize) -> Result<bool, BundleError> {
+        self.buffer.drain(..consumed);
+        match self.blocks.next() {
+            Some(block) => {
+                self.buffer.extend_from_slice(block?.as_slice());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn next_observation(&mut self) -> Result<Option<Observation>, BundleError> {
+        let mut consumed = 0usize;
+        loop {
+            let available = self.buffer.len() - consumed;
+            match std::mem::replace(&mut self.position, TarPosition::End) {
+                TarPosition::End => return Ok(None),
+                TarPosition::Header if available < TAR_BLOCK => {
+                    if self.fill(consumed)? {
+                        self.position = TarPosition::Header;
+                    }
+                    consumed = 0;
+                }
+                TarPosition::Header => {
+                    let header = &self.buffer[consumed..consumed + TAR_BLOCK];
+                    consumed += TAR_BLOCK;
+                    if header.iter().all(|byte| *byte == 0) {
+                        continue;
+                    }
+                    let name_end = header[0..100].iter().position(|byte| *byte == 0);
+                    let name = String::from_utf8_lossy(&header[0..name_end.unwrap_or(100)]);
+                    let size = parse_octal(&header[124..136])?;
+                    // regular files only, long names and directories are skipped
+                    let is_file = header[156] == b'0' || header[156] == 0;
+                    self.position = TarPosition::Entry {
+                        is_csv: is_file && name != MANIFEST_NAME,
+                        name: name.to_string(),
+                        remaining: size,
+                        padding: (TAR_BLOCK - size % TAR_BLOCK) % TAR_BLOCK,
+                        line: 0,
+                    };
+                }
+                TarPosition::Entry {
+                    remaining: 0,
+                    padding,
+                    ..
+                } if available < padding => {
+                    if !self.fill(self.buffer.len())? {
+                        return Err(truncated());
+                    }
+                    consumed = 0;
+                    self.position = TarPosition::Entry {
+                        name: String::new(),
+                        is_csv: false,
+                        remaining: 0,
+                        padding: padding - available,
+                        line: 0,
+                    };
+                }
+                TarPosition::Entry {
+                    remaining: 0,
+                    padding,
+                    ..
+                } => {
+                    consumed += padding;
+                    self.position = TarPosition::Header;
+                }
+                TarPosition::Entry {
+                    is_csv: false,
+                    remaining,
+                    padding,
+                    ..
+                } => {
+                    let skip = available.min(remaining);
+                    consumed += skip;
+                    if skip < remaining {
+                        if !self.fill(self.buffer.len())? {
+                            return Err(truncated());
+                        }
+                        consumed = 0;
+                    }
+                    self.position = TarPosition::Entry {
+                        name: String::new(),
+                        is_csv: false,
+                        remaining: remaining - skip,
+                        padding,
+                        line: 0,
+                    };
+                }
+                TarPosition::Entry {
+                    name,
+                    is_csv: true,
+                    remaining,
+                    padding,
+                    line,
+                } => {
+                    let in_entry = &self.buffer[consumed..consumed + available.min(remaining)];
+                    let line_length = match in_entry.iter().position(|byte| *byte == b'\n') {
+                        Some(newline) => newline + 1,
+                        // the last row of an entry may not end in a newline
+                        None if in_entry.len() == remaining => remaining,
+                        None => {
+                            if !self.fill(consumed)? {
+                                return Err(truncated());
+                            }
+                            consumed = 0;
+                            self.position = TarPosition::Entry {
+                                name,
+                                is_csv: true,
+                                remaining,
+                                padding,
+                                line,
+                            };
+                            continue;
+                        }
+                    };
+                    let row = String::from_utf8_lossy(&in_entry[..line_length]);
+                    let row = row.trim_end_matches(&['\r', '\n'][..]);
+                    self.record.clear();
+                    for field in row.split(',') {
+                        self.record.push_field(field);
+                    }
+                    consumed += line_length;
+                    let line = line + 1;
+                    let keep = !row.is_empty() && self.filter.matches(&self.record);
+                    let observation = if keep {
+                        Some(
+                            Observation::try_from(self.record.clone()).map_err(|source| {
+                                BundleError::Row {
+                                    name: name.clone(),
+                                    line,
+                                    source,
+                                }
+                            })?,
+                        )
+                    } else {
+                        None
+                    };
+                    self.position = TarPosition::Entry {
+                        name,
+                        is_csv: true,
+                        remaining: remaining - line_length,
+                        padding,
+                        line,
+                    };
+                    if observation.is_some() {
+                        self.buffer.drain(..consumed);
+                        return Ok(observation);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for ObservationStream<'a> {
+    type Item = Result<Observation, BundleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_observation() {
+            Ok(observation) => observation.map(Ok),
+            Err(e) => {
+                // nothing sensible follows a broken archive
+                self.position = TarPosition::End;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn truncated() -> BundleError {
+    BundleError::Container(String::from("archive ends inside a file"))
+}
+
+// tar sizes are octal ascii, padded with spaces or nuls
+fn parse_octal(field: &[u8]) -> Result<usize, BundleError> {
+    let digits = String::from_utf8_lossy(field);
+    let digits = digits.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(digits, 8)
+        .map_err(|_| BundleError::Container(format!("bad tar size {:?}", digits)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ObservationFilter, ObservationStream};
+    use crate::{
+        bundle::{BundleEntry, DatasetBundle, BUNDLE_PATH_LOCK, BUNDLE_PATH_VARIABLE},
+        compression::decompress_tar_file_to_csv_string,
+        observation::{DataRecording, Observation},
+    };
+    use chrono::NaiveDate;
+    use csv::{ReaderBuilder, StringRecord};
+    use lzma_rs::xz_compress;
+    static BLOCKS_TEST_OBJECT: &[u8] = include_bytes!("../test-fixtures/blocks.tar.xz");
+
+    fn all_observations(input: &[u8]) -> Vec<Observation> {
+        let csv = decompress_tar_file_to_csv_string(input);
+        ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv.as_slice())
+            .records()
+            .map(|record| Observation::try_from(record.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_every_row() {
+        let expected = all_observations(BLOCKS_TEST_OBJECT);
+        let streamed: Vec<Observation> =
+            ObservationStream::new(BLOCKS_TEST_OBJECT, ObservationFilter::new())
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(streamed.len(), 718);
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_stream_filtered() {
+        let start_date = NaiveDate::from_ymd_opt(2021, 12, 25).unwrap();
+        let end_date = NaiveDate::from_ymd_opt(2022, 1, 5).unwrap();
+        let mut filter = ObservationFilter::new();
+        filter
+            .station("sha")
+            .station("FOL")
+            .start_date(start_date)
+            .end_date(end_date);
+        let streamed: Vec<Observation> = ObservationStream::new(BLOCKS_TEST_OBJECT, filter.clone())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected: Vec<Observation> = all_observations(BLOCKS_TEST_OBJECT)
+            .into_iter()
+            .filter(|observation| {
+                let date = observation.date_observation.date();
+                ["SHA", "FOL"].contains(&observation.station_id.as_str())
+                    && start_date <= date
+                    && date <= end_date
+            })
+            .collect();
+        assert_eq!(streamed.len(), 24);
+        assert_eq!(streamed, expected);
+        let oro = StringRecord::from(vec![
+            "ORO",
+            "D",
+            "15",
+            "STORAGE",
+            "20220101 0000",
+            "20220101 0000",
+            "1",
+            "",
+            "AF",
+        ]);
+        assert!(!filter.matches(&oro));
+    }
+
+    #[test]
+    fn test_stream_current() {
+        let _lock = BUNDLE_PATH_LOCK.lock().unwrap();
+        let mut filter = ObservationFilter::new();
+        filter.station("VIL");
+        let first = ObservationStream::current(filter)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.station_id, "VIL");
+    }
+
+    #[test]
+    fn test_stream_current_newer_bundle() {
+        let _lock = BUNDLE_PATH_LOCK.lock().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        // a bundle of one VIL row, made the day after it
+        let bundle = |path: &str, date: NaiveDate| {
+            let path = directory.path().join(path);
+            let day = date.format("%Y%m%d 0000");
+            let entries = vec![BundleEntry {
+                name: String::from("vil.csv"),
+                contents: format!("VIL,D,15,STORAGE,{},{},1234,,AF\n", day, day).into_bytes(),
+            }];
+            let created = date.succ_opt().unwrap();
+            let tar = DatasetBundle::new(created, entries).to_tar().unwrap();
+            let mut xz = Vec::new();
+            xz_compress(&mut tar.as_slice(), &mut xz).unwrap();
+            std::fs::write(&path, xz).unwrap();
+            path
+        };
+        let first_vil = || {
+            let mut filter = ObservationFilter::new();
+            filter.station("VIL");
+            ObservationStream::current(filter)
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap()
+        };
+        // reaching further forward than the embedded bundle, it is streamed
+        let newer = bundle("newer.tar.xz", NaiveDate::from_ymd_opt(2030, 1, 1).unwrap());
+        std::env::set_var(BUNDLE_PATH_VARIABLE, &newer);
+        let first = first_vil();
+        assert_eq!(first.value, DataRecording::Recording(1234));
+        // one that is not newer is passed over
+        let older = bundle("older.tar.xz", NaiveDate::from_ymd_opt(2000, 1, 1).unwrap());
+        std::env::set_var(BUNDLE_PATH_VARIABLE, &older);
+        let first = first_vil();
+        std::env::remove_var(BUNDLE_PATH_VARIABLE);
+        assert_ne!(first.value, DataRecording::Recording(1234));
+    }
+}
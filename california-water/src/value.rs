@@ -0,0 +1,87 @@
+//! The numbers a sensor reports: whole acre feet of storage, or the
+//! fractional and negative values of sensors like elevation, temperature
+//! or change in storage.
+use serde::Serialize;
+use std::fmt;
+
+/// A value that `DataRecording::Recording` can hold.
+///
+/// Interpolation and aggregation work in `f64` and come back through
+/// `from_f64`, so that a value that goes down never underflows an
+/// unsigned type.
+pub trait SensorValue:
+    Copy + PartialEq + PartialOrd + fmt::Debug + fmt::Display + Serialize
+{
+    /// The value of a csv field, `None` when it is not a finite number of
+    /// this type, e.g. `1043.52` for `u32`.
+    fn parse(value: &str) -> Option<Self>;
+
+    fn to_f64(self) -> f64;
+
+    /// The closest value to `value`, integers rounding and saturating at
+    /// their bounds.
+    fn from_f64(value: f64) -> Self;
+}
+
+impl SensorValue for u32 {
+    fn parse(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round() as u32
+    }
+}
+
+impl SensorValue for i32 {
+    fn parse(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round() as i32
+    }
+}
+
+impl SensorValue for f64 {
+    fn parse(value: &str) -> Option<Self> {
+        value.parse::<f64>().ok().filter(|value| value.is_finite())
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SensorValue;
+
+    #[test]
+    fn test_sensor_value() {
+        assert_eq!(u32::parse("9593"), Some(9593));
+        assert_eq!(u32::parse("1043.52"), None);
+        assert_eq!(u32::parse("-4"), None);
+        assert_eq!(i32::parse("-4"), Some(-4));
+        assert_eq!(f64::parse("1043.52"), Some(1043.52));
+        assert_eq!(f64::parse("-3.5"), Some(-3.5));
+        assert_eq!(f64::parse("NaN"), None);
+        assert_eq!(f64::parse("inf"), None);
+        assert_eq!(u32::from_f64(2.5), 3);
+        assert_eq!(u32::from_f64(-10.0), 0);
+        assert_eq!(i32::from_f64(-2.4), -2);
+        assert_eq!(f64::from_f64(-2.4), -2.4);
+    }
+}
@@ -0,0 +1,155 @@
+//! California's water year, October through September, and storage
+//! summarized by it.
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+/// A water year, named for the calendar year it ends in: water year 2022
+/// runs from 2021-10-01 to 2022-09-30.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct WaterYear(pub i32);
+
+impl WaterYear {
+    pub fn from_date(date: &NaiveDate) -> Self {
+        if date.month() >= 10 {
+            WaterYear(date.year() + 1)
+        } else {
+            WaterYear(date.year())
+        }
+    }
+
+    pub fn year(&self) -> i32 {
+        self.0
+    }
+
+    /// October 1st of the previous calendar year.
+    pub fn first_day(&self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.0 - 1, 10, 1).unwrap()
+    }
+
+    /// September 30th, the day carryover storage is measured on.
+    pub fn last_day(&self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.0, 9, 30).unwrap()
+    }
+
+    pub fn contains(&self, date: &NaiveDate) -> bool {
+        WaterYear::from_date(date) == *self
+    }
+
+    pub fn num_days(&self) -> i64 {
+        (self.last_day() - self.first_day()).num_days() + 1
+    }
+
+    /// Days since the start of the water year, October 1st being day 1.
+    pub fn day_of_water_year(date: &NaiveDate) -> u32 {
+        let first_day = WaterYear::from_date(date).first_day();
+        (*date - first_day).num_days() as u32 + 1
+    }
+
+    /// Every water year that a date range touches, in order.
+    pub fn range(start_date: &NaiveDate, end_date: &NaiveDate) -> Vec<WaterYear> {
+        let first = WaterYear::from_date(start_date).0;
+        let last = WaterYear::from_date(end_date).0;
+        (first..=last).map(WaterYear).collect()
+    }
+}
+
+impl fmt::Display for WaterYear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WY{}", self.0)
+    }
+}
+
+/// Storage over one water year, from the days that have a value.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WaterYearSummary {
+    pub water_year: WaterYear,
+    pub days: usize,
+    pub min: u32,
+    pub max: u32,
+    pub mean: f64,
+    /// Storage on September 30th, `None` when that day has no value.
+    pub carryover: Option<u32>,
+}
+
+/// Summarizes daily storage by water year.
+pub fn summarize_water_years(
+    values: &BTreeMap<NaiveDate, u32>,
+) -> BTreeMap<WaterYear, WaterYearSummary> {
+    let mut by_water_year: BTreeMap<WaterYear, Vec<(NaiveDate, u32)>> = BTreeMap::new();
+    for (date, value) in values {
+        by_water_year
+            .entry(WaterYear::from_date(date))
+            .or_default()
+            .push((*date, *value));
+    }
+    by_water_year
+        .into_iter()
+        .map(|(water_year, days)| {
+            let sum: u64 = days.iter().map(|(_, value)| *value as u64).sum();
+            let summary = WaterYearSummary {
+                water_year,
+                days: days.len(),
+                min: days.iter().map(|(_, value)| *value).min().unwrap(),
+                max: days.iter().map(|(_, value)| *value).max().unwrap(),
+                mean: sum as f64 / days.len() as f64,
+                carryover: values.get(&water_year.last_day()).cloned(),
+            };
+            (water_year, summary)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{summarize_water_years, WaterYear};
+    use chrono::NaiveDate;
+    use std::collections::BTreeMap;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_water_year() {
+        assert_eq!(WaterYear::from_date(&date(2021, 9, 30)), WaterYear(2021));
+        assert_eq!(WaterYear::from_date(&date(2021, 10, 1)), WaterYear(2022));
+        assert_eq!(WaterYear(2022).first_day(), date(2021, 10, 1));
+        assert_eq!(WaterYear(2022).last_day(), date(2022, 9, 30));
+        assert_eq!(WaterYear(2022).num_days(), 365);
+        // February 2024 has a 29th
+        assert_eq!(WaterYear(2024).num_days(), 366);
+        assert!(WaterYear(2022).contains(&date(2022, 2, 15)));
+        assert_eq!(WaterYear::day_of_water_year(&date(2021, 10, 1)), 1);
+        assert_eq!(WaterYear::day_of_water_year(&date(2022, 1, 1)), 93);
+        assert_eq!(WaterYear::day_of_water_year(&date(2022, 9, 30)), 365);
+        assert_eq!(
+            WaterYear::range(&date(2021, 9, 1), &date(2022, 10, 1)),
+            vec![WaterYear(2021), WaterYear(2022), WaterYear(2023)]
+        );
+        assert_eq!(WaterYear(2022).to_string(), "WY2022");
+    }
+
+    #[test]
+    fn test_summarize_water_years() {
+        let values: BTreeMap<NaiveDate, u32> = vec![
+            (date(2021, 9, 29), 10),
+            (date(2021, 9, 30), 20),
+            (date(2021, 10, 1), 30),
+            (date(2022, 3, 1), 60),
+            (date(2022, 9, 29), 40),
+        ]
+        .into_iter()
+        .collect();
+        let summaries = summarize_water_years(&values);
+        assert_eq!(summaries.len(), 2);
+        let wy2021 = &summaries[&WaterYear(2021)];
+        assert_eq!(wy2021.days, 2);
+        assert_eq!(wy2021.carryover, Some(20));
+        assert_eq!(wy2021.mean, 15.0);
+        let wy2022 = &summaries[&WaterYear(2022)];
+        assert_eq!((wy2022.min, wy2022.max), (30, 60));
+        assert_eq!(wy2022.mean, 130.0 / 3.0);
+        assert_eq!(wy2022.carryover, None);
+    }
+}
@@ -1,18 +1,54 @@
-use california_water::{observation::Observation, reservoir::Reservoir};
+use california_water::{
+    bundle::{BundleEntry, DatasetBundle, Manifest, ManifestFile},
+    cache::ObservationCache,
+    chart::ChartOptions,
+    error::ObservationError,
+    export::{write_parquet, ArrowValue},
+    fill::DailyFill,
+    gap_fill::gap_fill_from_name,
+    observation::{
+        DataRecording, DateChunks, Duration, Observation, SensorValue, StationObservations,
+    },
+    quality::QualityChecks,
+    reservoir::Reservoir,
+    schedule::{FetchSchedule, ScheduledSource},
+    sensor::Sensor,
+    source::{CdecSource, ReqwestSource},
+    statewide::ReservoirObservations,
+};
+use crate::gfx::output::{draw_png, draw_svg};
 use chrono::NaiveDate;
 use core::panic;
-use csv::Writer;
+use csv::{StringRecord, Writer};
 use futures::future::join_all;
 use lzma_rs::lzma_decompress;
-use reqwest::Client;
+use serde::Serialize;
 use std::{
-    io::{BufReader, Write},
+    collections::HashMap,
+    io::{self, BufReader, BufWriter, Write},
     path::Path,
 };
 use tar::Archive;
+use xz2::{
+    stream::{Check, MtStreamBuilder},
+    write::XzEncoder,
+};
+
+// the name `get_all_records` has always found the snapshot rows under
+const SNAPSHOT_CSV_NAME: &str = "output.csv";
+const SNAPSHOT_BLOCK_SIZE: u64 = 1 << 20;
+
 pub struct AppBuilder {
     pub start_date: NaiveDate,
     pub end_date: Option<NaiveDate>,
+    pub sensor: Sensor,
+    pub schedule: FetchSchedule,
+    pub cache_directory: Option<String>,
+    pub chart: ChartOptions,
+    pub fill: bool,
+    pub clean: bool,
+    pub gap_fill: Option<String>,
+    pub max_gap: Option<usize>,
     pub filetype: Option<FileType>,
     pub filename: Option<String>,
     pub input_filename: Option<String>,
@@ -21,15 +57,27 @@ pub struct AppBuilder {
 #[derive(Clone)]
 pub enum FileType {
     PNG,
+    SVG,
     CSV,
     STDOUT,
     LZMA,
+    PARQUET,
+    JSON,
+    NDJSON,
 }
 
 #[derive(Clone)]
 pub struct App {
     pub start_date: NaiveDate,
     pub end_date: Option<NaiveDate>,
+    pub sensor: Sensor,
+    pub schedule: FetchSchedule,
+    pub cache_directory: Option<String>,
+    pub chart: ChartOptions,
+    pub fill: bool,
+    pub clean: bool,
+    pub gap_fill: Option<String>,
+    pub max_gap: Option<usize>,
     pub filetype: Option<FileType>,
     pub filename: Option<String>,
     pub input_filename: Option<String>,
@@ -43,6 +91,14 @@ impl App {
         let app_copy = App {
             start_date: self.start_date,
             end_date: self.end_date,
+            sensor: self.sensor,
+            schedule: self.schedule,
+            cache_directory: self.cache_directory,
+            chart: self.chart,
+            fill: self.fill,
+            clean: self.clean,
+            gap_fill: self.gap_fill.clone(),
+            max_gap: self.max_gap,
             filetype: self.filetype,
             filename: Some(fname),
             input_filename: Some(input_fname),
@@ -73,75 +129,513 @@ impl App {
         let app_copy = App {
             start_date: self.start_date,
             end_date: self.end_date,
+            sensor: self.sensor,
+            schedule: self.schedule,
+            cache_directory: self.cache_directory,
+            chart: self.chart,
+            fill: self.fill,
+            clean: self.clean,
+            gap_fill: self.gap_fill.clone(),
+            max_gap: self.max_gap,
             filetype: self.filetype,
             filename: Some(fname),
             input_filename: None,
         };
-        match app_copy.filetype.unwrap() {
-            FileType::CSV => {
-                let k = app_copy.filename.unwrap();
-                let file_name = k.as_str();
-                let p = Path::new(file_name);
-                let csv_out = App::run_csv(&app_copy.start_date, &app_copy.end_date.unwrap()).await;
-                let mut fs = std::fs::File::create(p).unwrap();
-                if fs.write_all(csv_out.as_bytes()).is_err() {
-                    panic!("writing csv file failed");
+        match app_copy.filetype.clone().unwrap() {
+            filetype @ (FileType::CSV | FileType::STDOUT | FileType::JSON | FileType::NDJSON)
+                if app_copy.fill =>
+            {
+                let filled = App::filled_observations(app_copy.fetch().await);
+                match filetype {
+                    FileType::JSON | FileType::NDJSON => app_copy.write_json_of(filetype, &filled),
+                    _ => app_copy.write_csv(filetype, App::filled_observations_to_csv(filled)),
                 }
             }
+            filetype @ (FileType::CSV
+            | FileType::STDOUT
+            | FileType::PARQUET
+            | FileType::JSON
+            | FileType::NDJSON) => {
+                // storage is whole acre feet, any other sensor may well have
+                // fractional or negative values
+                if app_copy.sensor.number == Sensor::storage().number {
+                    let observations = app_copy.observations(app_copy.fetch().await);
+                    app_copy.write_observations(filetype, observations);
+                } else {
+                    let observations = app_copy.observations(app_copy.fetch_as::<f64>().await);
+                    app_copy.write_observations(filetype, observations);
+                }
+            }
+            filetype @ (FileType::PNG | FileType::SVG) => {
+                let mut reservoir_observations = app_copy.fetch().await;
+                let output_filename = app_copy.filename.unwrap();
+                // filled days get marked on the chart
+                if let Some(gap_fill) = &app_copy.gap_fill {
+                    let strategy = gap_fill_from_name(gap_fill).expect("unknown gap fill");
+                    for series in reservoir_observations.series.iter_mut() {
+                        series.observations = Observation::smooth_observations_with(
+                            &series.observations,
+                            strategy.as_ref(),
+                            app_copy.max_gap,
+                        );
+                    }
+                }
+                let total = reservoir_observations.total();
+                let provenance = reservoir_observations.total_provenance();
+                let drawn = match filetype {
+                    FileType::PNG => {
+                        draw_png(output_filename, &total.values, &provenance, &app_copy.chart)
+                    }
+                    _ => draw_svg(output_filename, &total.values, &provenance, &app_copy.chart),
+                };
+                if let Err(e) = drawn {
+                    panic!("drawing chart failed: {}", e);
+                }
+            }
+            _ => {
+                panic!("error: needs to be either csv, stdout, json, ndjson, parquet, png or svg");
+            }
+        }
+    }
+
+    // writes csv to the output file, or to stdout
+    fn write_csv(&self, filetype: FileType, csv_out: String) {
+        match filetype {
             FileType::STDOUT => {
-                let csv_out = App::run_csv(&app_copy.start_date, &app_copy.end_date.unwrap()).await;
                 if std::io::stdout().write_all(csv_out.as_bytes()).is_err() {
                     panic!("stdout failed");
                 }
             }
-            FileType::PNG => {
-                // self.build_png().await;
-            }
             _ => {
-                panic!("error: needs to be either csv, stdout, or png");
+                let k = self.filename.as_ref().unwrap();
+                let p = Path::new(k.as_str());
+                let mut fs = std::fs::File::create(p).unwrap();
+                if fs.write_all(csv_out.as_bytes()).is_err() {
+                    panic!("writing csv file failed");
+                }
+            }
+        }
+    }
+
+    // writes observations of any sensor as csv, parquet, json or ndjson
+    fn write_observations<V: ArrowValue>(
+        &self,
+        filetype: FileType,
+        observations: Vec<Observation<V>>,
+    ) {
+        let output_filename = self.filename.clone().unwrap();
+        match filetype {
+            FileType::PARQUET => {
+                let fs = std::fs::File::create(output_filename).unwrap();
+                if let Err(e) = write_parquet(fs, observations.as_slice()) {
+                    panic!("writing parquet file failed: {}", e);
+                }
+            }
+            FileType::JSON | FileType::NDJSON => self.write_json_of(filetype, &observations),
+            _ => self.write_csv(filetype, App::observations_to_csv(observations)),
+        }
+    }
+
+    // writes rows as json or ndjson, to the output file or to stdout
+    fn write_json_of<T: Serialize>(&self, filetype: FileType, rows: &[T]) {
+        let output_filename = self.filename.clone().unwrap();
+        // `-` is stdout, for piping into jq
+        let output: Box<dyn Write> = if output_filename == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(std::fs::File::create(output_filename).unwrap())
+        };
+        let written = match filetype {
+            FileType::JSON => App::write_json(output, rows),
+            _ => App::write_ndjson(output, rows),
+        };
+        if let Err(e) = written {
+            panic!("writing json failed: {}", e);
+        }
+    }
+
+    // every observation fetched, smoothed when a gap fill is asked for
+    fn observations<V: SensorValue>(
+        &self,
+        reservoir_observations: ReservoirObservations<V>,
+    ) -> Vec<Observation<V>> {
+        let observations: Vec<Observation<V>> = reservoir_observations
+            .series
+            .into_iter()
+            .flat_map(|series| series.observations)
+            .collect();
+        match &self.gap_fill {
+            Some(gap_fill) => {
+                let strategy = gap_fill_from_name(gap_fill).expect("unknown gap fill");
+                Observation::smooth_observations_with(
+                    &observations,
+                    strategy.as_ref(),
+                    self.max_gap,
+                )
             }
+            None => observations,
         }
     }
 
-    async fn run_csv(start_date: &NaiveDate, end_date: &NaiveDate) -> String {
-        // 1. get observations from date range
+    // every reservoir fetched, values read as `V`
+    async fn fetch_as<V: SensorValue>(&self) -> ReservoirObservations<V> {
+        self.fetch_reservoir_observations(&self.sensor).await
+    }
+
+    // every reservoir's storage fetched, with implausible values reported
+    // when cleaning is asked for
+    async fn fetch(&self) -> ReservoirObservations {
+        let mut reservoir_observations = self.fetch_as::<u32>().await;
+        if self.clean {
+            // and replaced only when a gap fill says what with
+            let gap_fill = self.gap_fill.as_deref().unwrap_or("none");
+            let strategy = gap_fill_from_name(gap_fill).expect("unknown gap fill");
+            let report = reservoir_observations.replace_implausible(
+                &QualityChecks::default(),
+                strategy.as_ref(),
+                self.max_gap,
+            );
+            for replacement in &report.replacements {
+                eprintln!("{}", replacement);
+            }
+            if !report.replacements.is_empty() {
+                eprintln!(
+                    "warning: {} implausible values found, {} replaced",
+                    report.replacements.len(),
+                    report.replaced()
+                );
+            }
+        }
+        reservoir_observations
+    }
+
+    /// Builds a snapshot, returning whether it was written. A snapshot
+    /// missing the reservoirs that failed is only written when
+    /// `allow_partial` says so.
+    pub async fn run_snapshot_build(self, allow_partial: bool) -> bool {
+        // 1. every reservoir's storage, smoothed across missing readings
+        let reservoir_observations = self.fetch_reservoir_observations(&Sensor::storage()).await;
+        if !reservoir_observations.is_complete() && !allow_partial {
+            eprintln!("error: not writing a partial snapshot, see --allow_partial");
+            return false;
+        }
+        let observations: Vec<Observation> = reservoir_observations
+            .series
+            .into_iter()
+            .flat_map(|series| series.observations)
+            .collect();
+        let gap_fill = self.gap_fill.as_deref().unwrap_or("linear");
+        let strategy = gap_fill_from_name(gap_fill).expect("unknown gap fill");
+        let smoothed =
+            Observation::smooth_observations_with(&observations, strategy.as_ref(), self.max_gap);
+        // 2. the csv, packed as an xz compressed tar with its manifest
+        let csv_out = App::observations_to_csv(smoothed);
+        let created = chrono::offset::Local::now().date_naive();
+        let bundle = DatasetBundle::new(
+            created,
+            vec![BundleEntry {
+                name: String::from(SNAPSHOT_CSV_NAME),
+                contents: csv_out.into_bytes(),
+            }],
+        );
+        let tar = match bundle.to_tar() {
+            Ok(tar) => tar,
+            Err(e) => panic!("Error: packing snapshot failed: {}", e),
+        };
+        // 1MiB blocks so readers can decode the snapshot a block at a time
+        let stream = MtStreamBuilder::new()
+            .preset(9)
+            .block_size(SNAPSHOT_BLOCK_SIZE)
+            .check(Check::Crc64)
+            .encoder()
+            .expect("Error: xz encoder failed to start");
+        let mut encoder = XzEncoder::new_stream(Vec::new(), stream);
+        if encoder.write_all(tar.as_slice()).is_err() {
+            panic!("Error: compressing snapshot failed");
+        }
+        let compressed = encoder.finish().unwrap();
+        let output_filename = self.filename.unwrap();
+        if std::fs::write(&output_filename, compressed).is_err() {
+            panic!("writing snapshot failed");
+        }
+        App::print_manifest(&bundle.manifest);
+        true
+    }
+
+    /// Checks a snapshot against its manifest, returning whether it
+    /// matches.
+    pub fn run_snapshot_verify(input_filename: &str) -> bool {
+        let bundle = match DatasetBundle::load(input_filename) {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                eprintln!("{}: {}", input_filename, e);
+                return false;
+            }
+        };
+        // the checksums match, so also make sure the manifest tells the
+        // truth about what the files hold
+        for (file, entry) in bundle.manifest.files.iter().zip(bundle.entries.iter()) {
+            let described = ManifestFile::describe(entry.name.as_str(), entry.contents.as_slice());
+            if described != *file {
+                eprintln!(
+                    "{}: manifest does not describe {}, expected {:?}",
+                    input_filename, file.name, described
+                );
+                return false;
+            }
+        }
+        App::print_manifest(&bundle.manifest);
+        true
+    }
+
+    fn print_manifest(manifest: &Manifest) {
+        let date_or_dash = |date: Option<NaiveDate>| match date {
+            Some(date) => date.to_string(),
+            None => String::from("-"),
+        };
+        println!(
+            "version {} created {}: {} stations from {} to {}",
+            manifest.version,
+            manifest.created,
+            manifest.stations().len(),
+            date_or_dash(manifest.start_date()),
+            date_or_dash(manifest.end_date())
+        );
+        for file in manifest.files.iter() {
+            println!("{} {} rows sha256 {}", file.name, file.rows, file.sha256);
+        }
+    }
+
+    async fn fetch_reservoir_observations<V: SensorValue>(
+        &self,
+        sensor: &Sensor,
+    ) -> ReservoirObservations<V> {
+        // 1. get observations from date range, pacing the requests to CDEC
+        //    and only asking for what is not cached yet
         let reservoirs = Reservoir::get_reservoir_vector();
-        let client = Client::new();
+        let source = ScheduledSource::new(ReqwestSource::new(), self.schedule.clone());
+        let cache = self.cache_directory.as_ref().map(ObservationCache::new);
+        // the days between monthly values are filled in the same way as
+        // the gaps between daily ones
+        let gap_fill = self.gap_fill.as_deref().unwrap_or("linear");
+        let strategy = gap_fill_from_name(gap_fill).expect("unknown gap fill");
         let all_reservoir_observations = join_all(reservoirs.iter().map(|reservoir| {
-            let client_ref = &client;
-            let start_date_ref = start_date;
-            let end_date_ref = end_date;
+            let source_ref: &dyn CdecSource = &source;
+            let cache_ref = cache.as_ref();
+            let strategy_ref = strategy.as_ref();
+            let end_date = self.end_date.unwrap();
             async move {
-                Observation::get_string_records(
-                    client_ref,
-                    reservoir.station_id.as_str(),
-                    start_date_ref,
-                    end_date_ref,
-                )
-                .await
+                let get_observations = |duration: Duration| {
+                    App::get_observations_by_duration(
+                        source_ref,
+                        cache_ref,
+                        reservoir.station_id.as_str(),
+                        sensor,
+                        duration,
+                        self.start_date,
+                        end_date,
+                    )
+                };
+                let observations = get_observations(Duration::Daily).await?;
+                // a station without monthly values still has its daily ones
+                Ok(match get_observations(Duration::Monthly).await {
+                    Ok(monthly_observations) => StationObservations {
+                        observations: Observation::fill_from_monthly_observations_with(
+                            observations,
+                            monthly_observations,
+                            strategy_ref,
+                            self.max_gap,
+                        ),
+                        monthly_error: None,
+                    },
+                    Err(e) => StationObservations {
+                        observations,
+                        monthly_error: Some(e),
+                    },
+                })
             }
         }))
         .await;
+        let reservoir_observations = ReservoirObservations::from_stations(
+            reservoirs
+                .into_iter()
+                .zip(all_reservoir_observations)
+                .collect(),
+        );
+        // 2. report the stations that failed instead of giving up on the rest
+        if !reservoir_observations.is_complete() {
+            for failure in &reservoir_observations.failures {
+                eprintln!("{}: {}", failure.reservoir.station_id, failure.error);
+            }
+            eprintln!(
+                "warning: {} reservoirs failed, {:.1}% of total capacity is missing",
+                reservoir_observations.failures.len(),
+                reservoir_observations.missing_capacity_fraction() * 100.0
+            );
+        }
+        for failure in &reservoir_observations.monthly_failures {
+            eprintln!(
+                "warning: {}: monthly values not used: {}",
+                failure.reservoir.station_id, failure.error
+            );
+        }
+        reservoir_observations
+    }
+
+    // one duration of a station, from the cache when there is one
+    async fn get_observations_by_duration<V: SensorValue>(
+        source: &dyn CdecSource,
+        cache: Option<&ObservationCache>,
+        station_id: &str,
+        sensor: &Sensor,
+        duration: Duration,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+    ) -> Result<Vec<Observation<V>>, ObservationError> {
+        match cache {
+            Some(cache) => {
+                cache
+                    .get_observations_by_duration(
+                        source,
+                        station_id,
+                        sensor,
+                        &duration,
+                        &start_date,
+                        &end_date,
+                    )
+                    .await
+            }
+            None => {
+                Observation::get_observations_by_duration_in_chunks(
+                    source,
+                    station_id,
+                    sensor,
+                    &duration,
+                    &start_date,
+                    &end_date,
+                    &DateChunks::default(),
+                )
+                .await
+            }
+        }
+    }
+
+    // one array of every row
+    fn write_json<T: Serialize>(mut output: Box<dyn Write>, rows: &[T]) -> io::Result<()> {
+        serde_json::to_writer(&mut output, rows)?;
+        output.write_all(b"\n")?;
+        output.flush()
+    }
+
+    // one row a line
+    fn write_ndjson<T: Serialize>(output: Box<dyn Write>, rows: &[T]) -> io::Result<()> {
+        let mut output = BufWriter::new(output);
+        for row in rows {
+            serde_json::to_writer(&mut output, row)?;
+            output.write_all(b"\n")?;
+        }
+        output.flush()
+    }
+
+    fn observations_to_csv<V: SensorValue>(observations: Vec<Observation<V>>) -> String {
         let mut writer = Writer::from_writer(vec![]);
-        for reservoir_records in all_reservoir_observations {
-            let records = reservoir_records.unwrap();
-            // writer.write_byte_record(records.iter());
-            for record in records {
-                if writer.write_byte_record(record.as_byte_record()).is_err() {
-                    panic!("Error: writiing record failed");
-                }
+        for observation in observations {
+            let record = match StringRecord::try_from(observation) {
+                Ok(record) => record,
+                Err(e) => panic!("Error: converting observation failed: {}", e),
+            };
+            if writer.write_byte_record(record.as_byte_record()).is_err() {
+                panic!("Error: writiing record failed");
+            }
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+
+    // every observation with its `--fill` columns
+    fn filled_observations(
+        reservoir_observations: ReservoirObservations,
+    ) -> Vec<FilledObservation> {
+        let percent_full = reservoir_observations.percent_full();
+        let mut filled = Vec::new();
+        for series in reservoir_observations.series {
+            let fill: HashMap<NaiveDate, DailyFill> = series
+                .fill()
+                .into_iter()
+                .map(|daily_fill| (daily_fill.date, daily_fill))
+                .collect();
+            for observation in series.observations {
+                let date = observation.date_observation.date();
+                let daily_fill = match observation.value {
+                    DataRecording::Recording(_) => fill.get(&date),
+                    _ => None,
+                };
+                filled.push(FilledObservation {
+                    observation,
+                    percent_of_capacity: daily_fill
+                        .and_then(|daily_fill| daily_fill.percent_of_capacity),
+                    days_since_full: daily_fill.and_then(|daily_fill| daily_fill.days_since_full),
+                    drawdown: daily_fill.map(|daily_fill| daily_fill.drawdown),
+                    statewide_percent_full: percent_full.get(&date).copied(),
+                });
+            }
+        }
+        filled
+    }
+
+    // the observation columns, then the `--fill` columns, empty where there
+    // is no value
+    fn filled_observations_to_csv(filled: Vec<FilledObservation>) -> String {
+        let mut writer = Writer::from_writer(vec![]);
+        for row in filled {
+            let mut record = match StringRecord::try_from(row.observation) {
+                Ok(record) => record,
+                Err(e) => panic!("Error: converting observation failed: {}", e),
+            };
+            let percent = |percent: Option<f64>| percent.map(|percent| format!("{:.2}", percent));
+            let columns = [
+                percent(row.percent_of_capacity),
+                row.days_since_full.map(|days| days.to_string()),
+                row.drawdown.map(|drawdown| drawdown.to_string()),
+                percent(row.statewide_percent_full),
+            ];
+            for column in columns {
+                record.push_field(column.as_deref().unwrap_or(""));
+            }
+            if writer.write_byte_record(record.as_byte_record()).is_err() {
+                panic!("Error: writiing record failed");
             }
         }
         String::from_utf8(writer.into_inner().unwrap()).unwrap()
     }
 }
 
+// an observation with percent of capacity, days since full and drawdown
+// from the water year's peak where it is a recording, and statewide percent
+// full where any reservoir reported that day
+#[derive(Serialize)]
+struct FilledObservation {
+    #[serde(flatten)]
+    observation: Observation,
+    percent_of_capacity: Option<f64>,
+    days_since_full: Option<i64>,
+    drawdown: Option<u32>,
+    statewide_percent_full: Option<f64>,
+}
+
 impl AppBuilder {
     // set app configuration
     pub fn new(start_date: NaiveDate) -> Self {
         Self {
             start_date,
             end_date: None,
+            sensor: Sensor::storage(),
+            schedule: FetchSchedule::default(),
+            cache_directory: None,
+            chart: ChartOptions::default(),
+            fill: false,
+            clean: false,
+            gap_fill: None,
+            max_gap: None,
             filetype: None,
             filename: None,
             input_filename: None,
@@ -153,6 +647,46 @@ impl AppBuilder {
         self
     }
 
+    pub fn sensor(&mut self, sensor: Sensor) -> &mut Self {
+        self.sensor = sensor;
+        self
+    }
+
+    pub fn schedule(&mut self, schedule: FetchSchedule) -> &mut Self {
+        self.schedule = schedule;
+        self
+    }
+
+    pub fn cache_directory(&mut self, cache_directory: String) -> &mut Self {
+        self.cache_directory = Some(cache_directory);
+        self
+    }
+
+    pub fn chart(&mut self, chart: ChartOptions) -> &mut Self {
+        self.chart = chart;
+        self
+    }
+
+    pub fn fill(&mut self, fill: bool) -> &mut Self {
+        self.fill = fill;
+        self
+    }
+
+    pub fn clean(&mut self, clean: bool) -> &mut Self {
+        self.clean = clean;
+        self
+    }
+
+    pub fn gap_fill(&mut self, gap_fill: String) -> &mut Self {
+        self.gap_fill = Some(gap_fill);
+        self
+    }
+
+    pub fn max_gap(&mut self, max_gap: usize) -> &mut Self {
+        self.max_gap = Some(max_gap);
+        self
+    }
+
     pub fn filetype(&mut self, filetype: FileType) -> &mut Self {
         self.filetype = Some(filetype);
         self
@@ -171,6 +705,14 @@ impl AppBuilder {
         let mut app = App {
             start_date: self.start_date,
             end_date: None,
+            sensor: self.sensor.clone(),
+            schedule: self.schedule.clone(),
+            cache_directory: self.cache_directory.clone(),
+            chart: self.chart.clone(),
+            fill: self.fill,
+            clean: self.clean,
+            gap_fill: self.gap_fill.clone(),
+            max_gap: self.max_gap,
             filetype: None,
             filename: None,
             input_filename: None,
@@ -200,6 +742,14 @@ impl AppBuilder {
         let mut app = App {
             start_date: self.start_date,
             end_date: None,
+            sensor: self.sensor.clone(),
+            schedule: self.schedule.clone(),
+            cache_directory: self.cache_directory.clone(),
+            chart: self.chart.clone(),
+            fill: self.fill,
+            clean: self.clean,
+            gap_fill: self.gap_fill.clone(),
+            max_gap: self.max_gap,
             filetype: None,
             filename: None,
             input_filename: None,
@@ -212,7 +762,7 @@ impl AppBuilder {
             app.end_date = self.end_date;
         } else {
             // step 1.1
-            let today = chrono::offset::Local::today().naive_local();
+            let today = chrono::offset::Local::now().date_naive();
             if today < self.start_date {
                 panic!("Error: start date must be not be in the future.  Either today or earlier");
             }
@@ -233,7 +783,7 @@ impl AppBuilder {
         if self.filename.is_some() {
             let k = self.filename.as_ref().unwrap();
             app.filename = Some(String::from(k.as_str()));
-            app.filetype = Some(FileType::CSV);
+            app.filetype = self.filetype.clone();
         } else {
             app.filetype = Some(FileType::STDOUT);
         }
@@ -3,12 +3,14 @@ use clap::{Arg, Command};
 pub fn new_app() -> Command<'static> {
     let data = data_subcommand();
     let decompress = decompress_subcommand();
+    let snapshot = snapshot_subcommand();
     Command::new("Water Reservoir CLI Tool")
         .version("")
         .author("Clinton Bowen <clinton.bowen@gmail.com>")
         .about("Graphs Water Table")
         .subcommand(data)
         .subcommand(decompress)
+        .subcommand(snapshot)
 }
 
 fn data_subcommand() -> Command<'static> {
@@ -33,10 +35,75 @@ fn data_subcommand() -> Command<'static> {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("sensor")
+                .short('n')
+                .long("sensor")
+                .value_name("SENSOR_NUMBER")
+                .help("CDEC sensor number, e.g. 6 elevation, 23 outflow, 76 inflow. If not supplied; storage (15) is assumed.")
+                .required(false)
+                .takes_value(true),
+        )
+        .args(schedule_args())
+        .args(gap_fill_args())
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .value_name("PIXELS")
+                .help("width of a png or svg chart. If not supplied; 1024 is assumed.")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("height")
+                .long("height")
+                .value_name("PIXELS")
+                .help("height of a png or svg chart. If not supplied; 768 is assumed.")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("title")
+                .long("title")
+                .value_name("TITLE")
+                .help("title of a png or svg chart")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("x_label")
+                .long("x_label")
+                .value_name("LABEL")
+                .help("x axis label of a png or svg chart")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("y_label")
+                .long("y_label")
+                .value_name("LABEL")
+                .help("y axis label of a png or svg chart")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("fill")
+                .long("fill")
+                .help("adds percent of capacity, days since full, drawdown from the water year's peak and statewide percent full columns to csv, stdout, json or ndjson output")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("clean")
+                .long("clean")
+                .help("reports implausible storage, e.g. above capacity or a sudden jump, and replaces it using the gap fill when one is given")
+                .required(false)
+                .takes_value(false),
+        )
         .arg(
             Arg::new("filetype")
                 .short('t')
-                .long("file type: png, csv")
+                .long("file type: png, svg, csv, json, ndjson, parquet")
                 .help("png file name output")
                 .required(true)
                 .takes_value(true),
@@ -45,7 +112,7 @@ fn data_subcommand() -> Command<'static> {
             Arg::new("output")
                 .short('o')
                 .long("filename of output")
-                .help("filename of output")
+                .help("filename of output, - for stdout with json and ndjson")
                 .required(true)
                 .takes_value(true),
         )
@@ -80,3 +147,108 @@ fn decompress_subcommand() -> Command<'static> {
                 .takes_value(true),
         )
 }
+
+// how hard to push CDEC and where to keep what was fetched, shared by every
+// subcommand that fetches observations
+fn schedule_args() -> [Arg<'static>; 4] {
+    [
+        Arg::new("max_in_flight")
+            .long("max_in_flight")
+            .value_name("REQUESTS")
+            .help("most requests to CDEC waiting at once. If not supplied; 8 is assumed.")
+            .required(false)
+            .takes_value(true),
+        Arg::new("retries")
+            .long("retries")
+            .value_name("RETRIES")
+            .help("times a timed out or throttled request is retried. If not supplied; 3 is assumed.")
+            .required(false)
+            .takes_value(true),
+        Arg::new("rate")
+            .long("rate")
+            .value_name("REQUESTS_PER_SECOND")
+            .help("most requests to CDEC started per second, 0 for no limit. If not supplied; 10 is assumed.")
+            .required(false)
+            .takes_value(true),
+        Arg::new("cache")
+            .long("cache")
+            .value_name("DIRECTORY")
+            .help("directory to keep fetched observations in, only missing days are requested from CDEC.")
+            .required(false)
+            .takes_value(true),
+    ]
+}
+
+// how gaps between recordings are filled in, shared by every subcommand
+// that smooths observations
+fn gap_fill_args() -> [Arg<'static>; 2] {
+    [
+        Arg::new("gap_fill")
+            .long("gap_fill")
+            .value_name("STRATEGY")
+            .help("how missing observations are filled in: linear, step, monotone or none.")
+            .required(false)
+            .takes_value(true),
+        Arg::new("max_gap")
+            .long("max_gap")
+            .value_name("DAYS")
+            .help("longest gap, in days, that is filled in, between daily or monthly values. If not supplied; every gap is filled.")
+            .required(false)
+            .takes_value(true),
+    ]
+}
+
+fn snapshot_subcommand() -> Command<'static> {
+    let build = Command::new("build")
+        .about("fetches every reservoir and packs it as a snapshot like the embedded one")
+        .arg(
+            Arg::new("start_date")
+                .short('s')
+                .long("start_date")
+                .value_name("YYYYMMDD")
+                .help("start date of snapshot")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("end_date")
+                .short('e')
+                .long("end_date")
+                .value_name("YYYYMMDD")
+                .help("end date of snapshot. If not supplied; today's date is assumed.")
+                .required(false)
+                .takes_value(true),
+        )
+        .args(schedule_args())
+        .args(gap_fill_args())
+        .arg(
+            Arg::new("allow_partial")
+                .long("allow_partial")
+                .help("writes the snapshot even when some reservoirs failed to fetch")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("filename of output")
+                .help("filename of output, e.g. output.tar.lzma")
+                .required(true)
+                .takes_value(true),
+        );
+    let verify = Command::new("verify")
+        .about("checks a snapshot against its manifest")
+        .arg(
+            Arg::new("input")
+                .short('i')
+                .long("filename of input")
+                .help("filename of input")
+                .required(true)
+                .takes_value(true),
+        );
+    Command::new("snapshot")
+        .about("builds or checks a historical snapshot")
+        .subcommand_required(true)
+        .subcommand(build)
+        .subcommand(verify)
+}
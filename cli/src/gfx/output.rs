@@ -0,0 +1,30 @@
+use california_water::{
+    chart::{draw_storage_chart_with_provenance, ChartOptions},
+    observation::Provenance,
+};
+use chrono::NaiveDate;
+use plotters::prelude::*;
+use std::{collections::BTreeMap, error::Error, path::Path};
+
+/// Draws the statewide storage line chart the explorer draws to a png
+/// file, with the days that were not measured marked.
+pub fn draw_png<P: AsRef<Path>>(
+    path: P,
+    values: &BTreeMap<NaiveDate, u32>,
+    provenance: &BTreeMap<NaiveDate, Provenance>,
+    options: &ChartOptions,
+) -> Result<(), Box<dyn Error>> {
+    let backend = BitMapBackend::new(path.as_ref(), (options.width, options.height));
+    draw_storage_chart_with_provenance(&backend.into_drawing_area(), values, provenance, options)
+}
+
+/// Draws the statewide storage line chart to an svg file.
+pub fn draw_svg<P: AsRef<Path>>(
+    path: P,
+    values: &BTreeMap<NaiveDate, u32>,
+    provenance: &BTreeMap<NaiveDate, Provenance>,
+    options: &ChartOptions,
+) -> Result<(), Box<dyn Error>> {
+    let backend = SVGBackend::new(path.as_ref(), (options.width, options.height));
+    draw_storage_chart_with_provenance(&backend.into_drawing_area(), values, provenance, options)
+}
@@ -1,9 +1,14 @@
 pub mod cmd;
-use clap::ArgMatches;
+pub mod gfx;
+use clap::{ArgMatches, ErrorKind};
 
+use self::cmd::app::App;
 use self::cmd::app::AppBuilder;
 use self::cmd::app::FileType;
 use self::cmd::clap::new_app;
+use california_water::{
+    chart::ChartOptions, gap_fill::gap_fill_from_name, schedule::FetchSchedule, sensor::Sensor,
+};
 use chrono::{NaiveDate, Utc};
 
 #[tokio::main]
@@ -11,6 +16,7 @@ async fn main() -> Result<(), ()> {
     match new_app().get_matches().subcommand() {
         Some(("decompress", app)) => decompress_run(app).await,
         Some(("data", app)) => data_run(app).await,
+        Some(("snapshot", app)) => snapshot_run(app).await,
         _ => {
             panic!("needs to use subcommand")
         }
@@ -35,7 +41,7 @@ async fn decompress_run(app: &ArgMatches) -> Result<(), ()> {
         Some(value) => String::from(value),
         _ => String::new(),
     };
-    let now = Utc::now().date().naive_local();
+    let now = Utc::now().date_naive();
     let app = AppBuilder::new(now)
         .filetype(filetype)
         .filename(output)
@@ -46,36 +52,174 @@ async fn decompress_run(app: &ArgMatches) -> Result<(), ()> {
 }
 
 async fn data_run(app: &ArgMatches) -> Result<(), ()> {
-    let start_date = {
-        let start = app.value_of("start_date").expect("Needs a start date");
-        NaiveDate::parse_from_str(start, "%Y%m%d").expect("start date format must be YYYMMDD")
-    };
-    let end_date = {
-        let now = Utc::now().date().naive_local();
-        if let Some(end) = app.value_of("end_date") {
-            NaiveDate::parse_from_str(end, "%Y%m%d").expect("end date needs to be YYYYMMDD format")
-        } else {
-            now
-        }
+    let (start_date, end_date) = dates_from_args(app);
+    let sensor = match app.value_of("sensor") {
+        Some(number) => Sensor::from_number(
+            number
+                .parse::<u32>()
+                .expect("sensor needs to be a CDEC sensor number"),
+        ),
+        None => Sensor::storage(),
     };
+    let schedule = schedule_from_args(app);
     let filetype = match app.value_of("filetype") {
         Some("csv") => FileType::CSV,
         Some("png") => FileType::PNG,
+        Some("svg") => FileType::SVG,
         Some("stdout") => FileType::STDOUT,
         Some("lzma") => FileType::LZMA,
+        Some("parquet") => FileType::PARQUET,
+        Some("json") => FileType::JSON,
+        Some("ndjson") => FileType::NDJSON,
         _ => {
-            panic!("filetype must be set to either csv, png, stdout")
+            panic!("filetype must be set to either csv, png, svg, stdout, json, ndjson, parquet")
         }
     };
     let output = match app.value_of("output") {
         Some(value) => String::from(value),
         _ => String::new(),
     };
-    let app = AppBuilder::new(start_date)
+    let mut chart = ChartOptions::default();
+    if let Some(width) = app.value_of("width") {
+        chart.width = width.parse::<u32>().expect("width needs to be a number");
+    }
+    if let Some(height) = app.value_of("height") {
+        chart.height = height.parse::<u32>().expect("height needs to be a number");
+    }
+    chart.title = app.value_of("title").map(String::from);
+    chart.x_label = app.value_of("x_label").map(String::from);
+    chart.y_label = app.value_of("y_label").map(String::from);
+    let fill = app.is_present("fill");
+    let is_tabular = matches!(
+        filetype,
+        FileType::CSV | FileType::STDOUT | FileType::JSON | FileType::NDJSON
+    );
+    if fill && !is_tabular {
+        new_app()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--fill adds columns to csv, stdout, json or ndjson output only",
+            )
+            .exit();
+    }
+    if fill && sensor.number != Sensor::storage().number {
+        panic!("fill columns need the storage sensor, 15");
+    }
+    let clean = app.is_present("clean");
+    if clean && sensor.number != Sensor::storage().number {
+        panic!("clean checks storage, sensor 15");
+    }
+    let is_chart = matches!(filetype, FileType::PNG | FileType::SVG);
+    if is_chart && sensor.number != Sensor::storage().number {
+        panic!("charts are of statewide storage, sensor 15");
+    }
+    let mut builder = AppBuilder::new(start_date);
+    builder
         .end_date(end_date)
+        .sensor(sensor)
+        .schedule(schedule)
+        .chart(chart)
+        .fill(fill)
+        .clean(clean)
         .filetype(filetype)
-        .filename(output)
-        .build();
+        .filename(output);
+    gap_fill_from_args(app, &mut builder);
+    if let Some(cache_directory) = app.value_of("cache") {
+        builder.cache_directory(String::from(cache_directory));
+    }
+    let app = builder.build();
     app.run().await;
     Ok(())
 }
+
+async fn snapshot_run(app: &ArgMatches) -> Result<(), ()> {
+    match app.subcommand() {
+        Some(("build", app)) => {
+            let (start_date, end_date) = dates_from_args(app);
+            let output = String::from(app.value_of("output").expect("Needs an output filename"));
+            let mut builder = AppBuilder::new(start_date);
+            builder
+                .end_date(end_date)
+                .schedule(schedule_from_args(app))
+                .filetype(FileType::LZMA)
+                .filename(output);
+            gap_fill_from_args(app, &mut builder);
+            if let Some(cache_directory) = app.value_of("cache") {
+                builder.cache_directory(String::from(cache_directory));
+            }
+            let allow_partial = app.is_present("allow_partial");
+            let app = builder.build();
+            if app.run_snapshot_build(allow_partial).await {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+        Some(("verify", app)) => {
+            let input = app.value_of("input").expect("Needs an input filename");
+            if App::run_snapshot_verify(input) {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+        _ => {
+            panic!("needs to use either build or verify")
+        }
+    }
+}
+
+fn dates_from_args(app: &ArgMatches) -> (NaiveDate, NaiveDate) {
+    let start_date = {
+        let start = app.value_of("start_date").expect("Needs a start date");
+        NaiveDate::parse_from_str(start, "%Y%m%d").expect("start date format must be YYYMMDD")
+    };
+    let end_date = {
+        let now = Utc::now().date_naive();
+        if let Some(end) = app.value_of("end_date") {
+            NaiveDate::parse_from_str(end, "%Y%m%d").expect("end date needs to be YYYYMMDD format")
+        } else {
+            now
+        }
+    };
+    (start_date, end_date)
+}
+
+fn schedule_from_args(app: &ArgMatches) -> FetchSchedule {
+    let mut schedule = FetchSchedule::default();
+    if let Some(max_in_flight) = app.value_of("max_in_flight") {
+        schedule.max_in_flight(
+            max_in_flight
+                .parse::<usize>()
+                .expect("max_in_flight needs to be a number"),
+        );
+    }
+    if let Some(retries) = app.value_of("retries") {
+        schedule.max_retries(
+            retries
+                .parse::<u32>()
+                .expect("retries needs to be a number"),
+        );
+    }
+    if let Some(rate) = app.value_of("rate") {
+        let rate = rate.parse::<f64>().expect("rate needs to be a number");
+        schedule.requests_per_second(if rate > 0.0 { Some(rate) } else { None });
+    }
+    schedule
+}
+
+fn gap_fill_from_args(app: &ArgMatches, builder: &mut AppBuilder) {
+    if let Some(gap_fill) = app.value_of("gap_fill") {
+        if gap_fill_from_name(gap_fill).is_none() {
+            panic!("gap_fill must be set to either linear, step, monotone or none");
+        }
+        builder.gap_fill(String::from(gap_fill));
+    }
+    if let Some(max_gap) = app.value_of("max_gap") {
+        builder.max_gap(
+            max_gap
+                .parse::<usize>()
+                .expect("max_gap needs to be a number"),
+        );
+    }
+}
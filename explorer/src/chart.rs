@@ -1,13 +1,16 @@
 use crate::date::DateWrapper;
-use california_water::observation::Observation;
-use chrono::{Datelike, NaiveDate};
-use easy_cast::Cast;
+use california_water::{
+    chart::{draw_storage_chart, ChartOptions},
+    observation::Observation,
+    schedule::{FetchSchedule, ScheduledSource},
+    source::ReqwestSource,
+};
+use chrono::NaiveDate;
 use js_sys::Date;
 use plotters::prelude::*;
 use plotters_canvas::CanvasBackend;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
-use std::ops::Range;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::HtmlCanvasElement;
@@ -45,12 +48,19 @@ impl Chart {
             let end_wrapper = DateWrapper::new(end_date_js);
             let start_date = NaiveDate::try_from(start_wrapper).unwrap();
             let end_date = NaiveDate::try_from(end_wrapper).unwrap();
-            if let Ok(observations) =
-                Observation::get_all_reservoirs_data_by_dates(&start_date, &end_date).await
-            {
+            let source = ScheduledSource::new(ReqwestSource::new(), FetchSchedule::default());
+            // chart whatever reservoirs answered, even if some failed
+            let reservoir_observations = Observation::get_all_reservoirs_observations_by_dates(
+                &source,
+                &start_date,
+                &end_date,
+            )
+            .await;
+            let total = reservoir_observations.total();
+            if !total.values.is_empty() {
                 // reservoir all the things
                 let reservoir_chart = ReservoirObservationChart {
-                    data_btree: observations,
+                    data_btree: total.values,
                     canvas,
                 };
                 reservoir_chart.chart().unwrap();
@@ -67,65 +77,21 @@ impl Chart {
 
 impl ReservoirObservationChart {
     fn chart(self) -> DrawResult<()> /* Result<Chart, JsValue> */ {
-        let dates: Vec<NaiveDate> = self.data_btree.keys().cloned().collect();
-        let start_date = dates.as_slice().first().unwrap();
-        let end_date = dates.as_slice().last().unwrap();
-        //Goal get max and min value of btree:
-        let date_range = Range {
-            start: *start_date,
-            end: *end_date,
-        };
-        let ranged_date: RangedDate<NaiveDate> = date_range.into();
-        let values = self.data_btree.values().cloned().collect::<Vec<u32>>();
-        let y_max: f64 = (*values.iter().max().unwrap() as i64).cast();
-        let y_min: f64 = (*values.iter().min().unwrap() as i64).cast();
-        let _x_max = values.len() as f64;
-        let x_labels_amount = (end_date.year() - start_date.year()) as usize;
-        // setup chart
         // setup canvas drawing area
         let backend = CanvasBackend::with_canvas_object(self.canvas).unwrap();
-        let backend_drawing_area = backend.into_drawing_area();
-        backend_drawing_area.fill(&WHITE).unwrap();
-        let mut chart = ChartBuilder::on(&backend_drawing_area)
-            .margin(20i32)
-            .x_label_area_size(10u32)
-            .y_label_area_size(10u32)
-            .build_cartesian_2d(ranged_date, y_min..y_max)
-            .unwrap();
-        // .build_cartesian_2d(-2.1..0.6, -1.2..1.2)?;
-
-        chart
-            .configure_mesh()
-            .x_labels(x_labels_amount)
-            // .disable_x_mesh()
-            // .disable_y_mesh()
-            .draw()?;
-
-        // populate the canvas with the data
-        chart
-            .draw_series(LineSeries::new(
-                self.data_btree
-                    .iter()
-                    .map(|x| (*x.0, *x.1 as i32 as f64))
-                    .collect::<Vec<_>>(),
-                &RED,
-            ))
-            .unwrap()
-            .label("water")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &RED));
-
-        chart
-            .configure_series_labels()
-            .background_style(&WHITE.mix(0.8))
-            .border_style(&BLACK)
-            .draw()
-            .unwrap();
-        backend_drawing_area.present().unwrap();
+        draw_storage_chart(
+            &backend.into_drawing_area(),
+            &self.data_btree,
+            &ChartOptions {
+                x_label_area_size: 10,
+                y_label_area_size: 10,
+                ..ChartOptions::default()
+            },
+        )
         // let boxed_chart_transform = Box::new(chart.into_coord_trans())
         // .map_err(|err| err.to_string())?;
         // Ok(Chart {
         //     convert: Box::new(boxed_chart_transform),
         // })
-        Ok(())
     }
 }